@@ -0,0 +1,381 @@
+use crate::config::AlertRule;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+}
+impl Op {
+    fn eval(self, a: f64, b: f64) -> bool {
+        match self {
+            Op::Gt => a > b,
+            Op::Ge => a >= b,
+            Op::Lt => a < b,
+            Op::Le => a <= b,
+            Op::Eq => a == b,
+            Op::Ne => a != b,
+        }
+    }
+}
+#[derive(Debug, Clone)]
+struct Segment {
+    field: String,
+    each: bool,
+}
+#[derive(Debug, Clone)]
+enum Condition {
+    Metric {
+        kind: String,
+        path: Vec<Segment>,
+        op: Op,
+        threshold: f64,
+        percent: bool,
+    },
+    ProcessExited {
+        name: String,
+    },
+}
+fn parse_condition(expr: &str) -> Result<Condition> {
+    let expr = expr.trim();
+    if let Some(arg) = expr
+        .strip_prefix("process_exited(")
+        .and_then(|r| r.strip_suffix(')'))
+    {
+        let name = arg.trim().trim_matches('"').trim();
+        if name.is_empty() {
+            bail!("process_exited() needs a process name");
+        }
+        return Ok(Condition::ProcessExited { name: name.into() });
+    }
+    let at = expr
+        .find(['<', '>', '=', '!'])
+        .context("expected a comparison (>, >=, <, <=, ==, !=)")?;
+    let (lhs, rest) = expr.split_at(at);
+    let (op, rhs) = match rest.get(..2) {
+        Some(">=") => (Op::Ge, &rest[2..]),
+        Some("<=") => (Op::Le, &rest[2..]),
+        Some("==") => (Op::Eq, &rest[2..]),
+        Some("!=") => (Op::Ne, &rest[2..]),
+        _ if rest.starts_with('>') => (Op::Gt, &rest[1..]),
+        _ if rest.starts_with('<') => (Op::Lt, &rest[1..]),
+        _ => bail!("unknown operator in `{expr}`"),
+    };
+    let rhs = rhs.trim();
+    let (num, percent) = match rhs.strip_suffix('%') {
+        Some(n) => (n.trim(), true),
+        None => (rhs, false),
+    };
+    let threshold: f64 = num
+        .parse()
+        .with_context(|| format!("threshold `{rhs}` is not a number"))?;
+    let mut parts = lhs.trim().split('.');
+    let kind = parts.next().unwrap_or_default().to_string();
+    let mut path = Vec::new();
+    for p in parts {
+        let (field, each) = match p.strip_suffix("[*]") {
+            Some(f) => (f, true),
+            None => (p, false),
+        };
+        if field.is_empty() || field.contains(['[', ']']) {
+            bail!("bad path segment `{p}` in `{}`", lhs.trim());
+        }
+        path.push(Segment {
+            field: field.into(),
+            each,
+        });
+    }
+    if kind.is_empty() || path.is_empty() {
+        bail!("expected `<kind>.<field>` on the left of `{expr}`");
+    }
+    if percent && path.last().is_some_and(|s| s.each) {
+        bail!("`%` thresholds need a field next to a `total`, not an array");
+    }
+    Ok(Condition::Metric {
+        kind,
+        path,
+        op,
+        threshold,
+        percent,
+    })
+}
+fn resolve(v: &Value, path: &[Segment], percent: bool, label: &str, out: &mut Vec<(String, f64)>) {
+    let Some((seg, rest)) = path.split_first() else {
+        return;
+    };
+    let Some(child) = v.get(&seg.field) else {
+        return;
+    };
+    let items: Vec<(String, &Value)> = if seg.each {
+        child
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .enumerate()
+                    .map(|(i, item)| {
+                        let l = item
+                            .get("name")
+                            .and_then(Value::as_str)
+                            .map(str::to_string)
+                            .unwrap_or_else(|| i.to_string());
+                        if label.is_empty() {
+                            (l, item)
+                        } else {
+                            (format!("{label}/{l}"), item)
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    } else {
+        vec![(label.to_string(), child)]
+    };
+    for (l, item) in items {
+        if !rest.is_empty() {
+            resolve(item, rest, percent, &l, out);
+            continue;
+        }
+        let Some(x) = item.as_f64() else {
+            continue;
+        };
+        if !percent {
+            out.push((l, x));
+        } else if let Some(total) = v.get("total").and_then(Value::as_f64).filter(|t| *t > 0.0) {
+            out.push((l, x / total * 100.0));
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertEvent {
+    pub rule_id: String,
+    pub state: AlertState,
+    pub expr: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub series: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f64>,
+}
+struct Compiled {
+    rule: AlertRule,
+    cond: Condition,
+    hold: Duration,
+}
+#[derive(Default)]
+struct Series {
+    pending_since: Option<Instant>,
+    firing: bool,
+}
+pub struct AlertEngine {
+    rules: Vec<Compiled>,
+    series: HashMap<(usize, String), Series>,
+    seen_procs: HashSet<String>,
+}
+impl AlertEngine {
+    pub fn new(rules: &[AlertRule]) -> Result<Self> {
+        let mut ids = HashSet::new();
+        let mut compiled = Vec::new();
+        for r in rules {
+            if r.id.trim().is_empty() {
+                bail!("alert rule with `when = \"{}\"` has no id", r.when);
+            }
+            if !ids.insert(r.id.as_str()) {
+                bail!("duplicate alert rule id `{}`", r.id);
+            }
+            let cond =
+                parse_condition(&r.when).with_context(|| format!("alert rule `{}`", r.id))?;
+            compiled.push(Compiled {
+                rule: r.clone(),
+                cond,
+                hold: Duration::from_secs(r.for_secs),
+            });
+        }
+        Ok(Self {
+            rules: compiled,
+            series: HashMap::new(),
+            seen_procs: HashSet::new(),
+        })
+    }
+    pub fn observe(&mut self, kind: &str, body: &Value, now: Instant) -> Vec<AlertEvent> {
+        let mut out = Vec::new();
+        for i in 0..self.rules.len() {
+            let Condition::Metric {
+                kind: k,
+                path,
+                op,
+                threshold,
+                percent,
+            } = &self.rules[i].cond
+            else {
+                continue;
+            };
+            if k != kind {
+                continue;
+            }
+            let (op, threshold) = (*op, *threshold);
+            let mut samples = Vec::new();
+            resolve(body, path, *percent, "", &mut samples);
+            for (series, value) in samples {
+                let active = op.eval(value, threshold);
+                self.step(i, series, active, Some(value), now, &mut out);
+            }
+        }
+        out
+    }
+    pub fn observe_processes(&mut self, names: &HashSet<String>, now: Instant) -> Vec<AlertEvent> {
+        let mut out = Vec::new();
+        for i in 0..self.rules.len() {
+            let Condition::ProcessExited { name } = &self.rules[i].cond else {
+                continue;
+            };
+            let name = name.clone();
+            let active = if names.contains(&name) {
+                self.seen_procs.insert(name.clone());
+                false
+            } else {
+                self.seen_procs.contains(&name)
+            };
+            self.step(i, name, active, None, now, &mut out);
+        }
+        out
+    }
+    fn step(
+        &mut self,
+        idx: usize,
+        series: String,
+        active: bool,
+        value: Option<f64>,
+        now: Instant,
+        out: &mut Vec<AlertEvent>,
+    ) {
+        let rule = &self.rules[idx];
+        let st = self.series.entry((idx, series.clone())).or_default();
+        let state = if active {
+            let since = *st.pending_since.get_or_insert(now);
+            if st.firing || now.duration_since(since) < rule.hold {
+                return;
+            }
+            st.firing = true;
+            AlertState::Firing
+        } else {
+            st.pending_since = None;
+            if !st.firing {
+                return;
+            }
+            st.firing = false;
+            AlertState::Resolved
+        };
+        out.push(AlertEvent {
+            rule_id: rule.rule.id.clone(),
+            state,
+            expr: rule.rule.when.clone(),
+            series: Some(series).filter(|s| !s.is_empty()),
+            value,
+            threshold: match rule.cond {
+                Condition::Metric { threshold, .. } => Some(threshold),
+                Condition::ProcessExited { .. } => None,
+            },
+        });
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    fn rule(id: &str, when: &str, for_secs: u64) -> AlertRule {
+        AlertRule {
+            id: id.into(),
+            when: when.into(),
+            for_secs,
+        }
+    }
+    #[test]
+    fn cpu_rule_fires_after_hold_and_resolves() {
+        let mut e =
+            AlertEngine::new(&[rule("cpu-hot", "cpu.global_cpu_percent > 90", 60)]).unwrap();
+        let t0 = Instant::now();
+        let hot = json!({"global_cpu_percent": 95.0});
+        assert!(e.observe("cpu", &hot, t0).is_empty());
+        assert!(e
+            .observe("cpu", &hot, t0 + Duration::from_secs(30))
+            .is_empty());
+        let fired = e.observe("cpu", &hot, t0 + Duration::from_secs(60));
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].state, AlertState::Firing);
+        assert_eq!(fired[0].value, Some(95.0));
+        assert!(e
+            .observe("cpu", &hot, t0 + Duration::from_secs(65))
+            .is_empty());
+        let cool = json!({"global_cpu_percent": 10.0});
+        let resolved = e.observe("cpu", &cool, t0 + Duration::from_secs(70));
+        assert_eq!(resolved[0].state, AlertState::Resolved);
+        assert_eq!(resolved[0].rule_id, "cpu-hot");
+    }
+    #[test]
+    fn short_spike_does_not_fire() {
+        let mut e = AlertEngine::new(&[rule("cpu-hot", "cpu.global_cpu_percent>90", 60)]).unwrap();
+        let t0 = Instant::now();
+        e.observe("cpu", &json!({"global_cpu_percent": 99.0}), t0);
+        e.observe(
+            "cpu",
+            &json!({"global_cpu_percent": 5.0}),
+            t0 + Duration::from_secs(30),
+        );
+        let out = e.observe(
+            "cpu",
+            &json!({"global_cpu_percent": 99.0}),
+            t0 + Duration::from_secs(61),
+        );
+        assert!(out.is_empty());
+    }
+    #[test]
+    fn disk_percent_rule_is_per_mount() {
+        let mut e =
+            AlertEngine::new(&[rule("disk-low", "disk.mounts[*].available < 5%", 0)]).unwrap();
+        let body = json!({"mounts": [
+            {"name": "/", "total": 100, "available": 50},
+            {"name": "/var", "total": 200, "available": 4},
+        ]});
+        let out = e.observe("disk", &body, Instant::now());
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].series.as_deref(), Some("/var"));
+        assert_eq!(out[0].value, Some(2.0));
+    }
+    #[test]
+    fn process_exit_fires_only_after_seen() {
+        let mut e = AlertEngine::new(&[rule("sshd-gone", "process_exited(\"sshd\")", 0)]).unwrap();
+        let t0 = Instant::now();
+        let none = HashSet::new();
+        let with: HashSet<String> = ["sshd".to_string()].into();
+        assert!(e.observe_processes(&none, t0).is_empty());
+        assert!(e.observe_processes(&with, t0).is_empty());
+        assert_eq!(e.observe_processes(&none, t0)[0].state, AlertState::Firing);
+        assert_eq!(
+            e.observe_processes(&with, t0)[0].state,
+            AlertState::Resolved
+        );
+    }
+    #[test]
+    fn invalid_rules_are_rejected() {
+        assert!(AlertEngine::new(&[rule("a", "cpu.global_cpu_percent ~ 3", 0)]).is_err());
+        assert!(AlertEngine::new(&[rule("a", "cpu > 3", 0)]).is_err());
+        assert!(AlertEngine::new(&[rule("a", "cpu.x > lots", 0)]).is_err());
+        assert!(
+            AlertEngine::new(&[rule("a", "cpu.x > 1", 0), rule("a", "mem.used > 1", 0)]).is_err()
+        );
+    }
+}
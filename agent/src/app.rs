@@ -0,0 +1,64 @@
+use crate::{
+    collectors::run_collect_loop,
+    config::{AgentConfig, CliOverrides},
+    health,
+    logging::LogHandle,
+    reload::spawn_config_reloader,
+    shutdown::Shutdown,
+};
+use anyhow::Result;
+use std::{path::PathBuf, time::Duration};
+use tracing::{info, warn};
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+/// Where the running config came from, so reloads re-read the same layers.
+pub struct RunOptions {
+    pub config: Option<PathBuf>,
+    pub config_optional: bool,
+    pub overrides: CliOverrides,
+}
+/// Runs the agent until `shutdown` fires, then waits for the final queue flush.
+/// Shared by the foreground `run` command and the Windows service entry point.
+pub async fn run(
+    cfg: AgentConfig,
+    opts: RunOptions,
+    log: LogHandle,
+    shutdown: Shutdown,
+) -> Result<()> {
+    health::init();
+    let cfg_rx = spawn_config_reloader(
+        opts.config,
+        opts.config_optional,
+        opts.overrides,
+        cfg.clone(),
+    )?;
+    #[cfg(feature = "status")]
+    if cfg.status.listen_addr().is_some() {
+        crate::transport::status::spawn_status_server(
+            &cfg.status,
+            cfg_rx.clone(),
+            shutdown.clone(),
+        )
+        .await?;
+    }
+    log.follow(cfg_rx.clone());
+    #[cfg(feature = "networking")]
+    if cfg.networking.enabled {
+        crate::transport::enroll::spawn_renewal(cfg_rx.clone(), shutdown.clone()).await;
+    }
+    #[cfg(feature = "networking")]
+    let net_tx =
+        crate::transport::modu::maybe_spawn_network_sender(&cfg, cfg_rx.clone(), shutdown.clone())
+            .await?;
+    run_collect_loop(
+        cfg_rx,
+        #[cfg(feature = "networking")]
+        net_tx,
+        shutdown.clone(),
+    )
+    .await?;
+    if !shutdown.drain(SHUTDOWN_GRACE).await {
+        warn!("shutdown grace period elapsed; exiting with tasks still running");
+    }
+    info!("agent stopped");
+    Ok(())
+}
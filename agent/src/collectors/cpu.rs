@@ -10,7 +10,7 @@ pub struct CpuStats {
 pub fn collect(sys: &mut System) -> CpuStats {
     sys.refresh_specifics(RefreshKind::new().with_cpu(CpuRefreshKind::everything()));
     let cpu = sys.global_cpu_info().cpu_usage();
-    let la = sys.load_average();
+    let la = System::load_average();
     CpuStats {
         global_cpu_percent: cpu,
         load_avg_one: la.one,
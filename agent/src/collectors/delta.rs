@@ -0,0 +1,165 @@
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Emit,
+    Heartbeat { unchanged_since: String },
+    Skip,
+}
+struct Seen {
+    hash: u64,
+    changed_at: String,
+    last_emit: Instant,
+}
+pub struct ChangeTracker {
+    kinds: Vec<String>,
+    ignore: Vec<String>,
+    heartbeat: Duration,
+    seen: HashMap<String, Seen>,
+}
+impl ChangeTracker {
+    pub fn new(kinds: Vec<String>, ignore: Vec<String>, heartbeat: Duration) -> Self {
+        Self {
+            kinds,
+            ignore,
+            heartbeat,
+            seen: HashMap::new(),
+        }
+    }
+    pub fn check(&mut self, kind: &str, body: &Value, ts: &str, now: Instant) -> Decision {
+        if !self.kinds.iter().any(|k| k == kind) {
+            return Decision::Emit;
+        }
+        let hash = self.hash_body(kind, body);
+        match self.seen.get_mut(kind) {
+            Some(s) if s.hash == hash => {
+                if now.duration_since(s.last_emit) < self.heartbeat {
+                    return Decision::Skip;
+                }
+                s.last_emit = now;
+                Decision::Heartbeat {
+                    unchanged_since: s.changed_at.clone(),
+                }
+            }
+            _ => {
+                self.seen.insert(
+                    kind.to_string(),
+                    Seen {
+                        hash,
+                        changed_at: ts.to_string(),
+                        last_emit: now,
+                    },
+                );
+                Decision::Emit
+            }
+        }
+    }
+    fn hash_body(&self, kind: &str, body: &Value) -> u64 {
+        let mut v = body.clone();
+        for path in &self.ignore {
+            if let Some(field) = path
+                .strip_prefix(kind)
+                .and_then(|rest| rest.strip_prefix('.'))
+            {
+                strip(&mut v, &field.split('.').collect::<Vec<_>>());
+            }
+        }
+        let mut h = DefaultHasher::new();
+        v.to_string().hash(&mut h);
+        h.finish()
+    }
+}
+/// Removes the field at `path`; a `name[*]` segment steps into every element of
+/// the array `name`, as in `mounts[*].available`.
+fn strip(v: &mut Value, path: &[&str]) {
+    let (Some((first, rest)), Value::Object(map)) = (path.split_first(), v) else {
+        return;
+    };
+    match first.strip_suffix("[*]") {
+        None if rest.is_empty() => {
+            map.remove(*first);
+        }
+        None => {
+            if let Some(child) = map.get_mut(*first) {
+                strip(child, rest);
+            }
+        }
+        Some(field) => {
+            if let Some(Value::Array(items)) = map.get_mut(field) {
+                for item in items {
+                    strip(item, rest);
+                }
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    fn tracker() -> ChangeTracker {
+        ChangeTracker::new(
+            vec!["os".into(), "disk".into()],
+            vec!["os.uptime_secs".into(), "disk.mounts[*].available".into()],
+            Duration::from_secs(300),
+        )
+    }
+    #[test]
+    fn skips_unchanged_bodies_until_the_heartbeat() {
+        let mut t = tracker();
+        let t0 = Instant::now();
+        let os = |uptime, host| json!({ "host_name": host, "uptime_secs": uptime });
+        assert_eq!(t.check("os", &os(1, "a"), "T0", t0), Decision::Emit);
+        let at = |secs| t0 + Duration::from_secs(secs);
+        assert_eq!(t.check("os", &os(2, "a"), "T1", at(10)), Decision::Skip);
+        assert_eq!(
+            t.check("os", &os(3, "a"), "T2", at(300)),
+            Decision::Heartbeat {
+                unchanged_since: "T0".into()
+            }
+        );
+        assert_eq!(t.check("os", &os(4, "a"), "T3", at(310)), Decision::Skip);
+        assert_eq!(t.check("os", &os(5, "b"), "T4", at(320)), Decision::Emit);
+        assert_eq!(
+            t.check("os", &os(6, "b"), "T5", at(620)),
+            Decision::Heartbeat {
+                unchanged_since: "T4".into()
+            }
+        );
+        assert_eq!(
+            t.check("cpu", &json!({"x": 1}), "T6", at(620)),
+            Decision::Emit
+        );
+        assert_eq!(
+            t.check("cpu", &json!({"x": 1}), "T7", at(621)),
+            Decision::Emit
+        );
+    }
+    #[test]
+    fn ignores_nested_fields_in_every_array_element() {
+        let mut t = tracker();
+        let t0 = Instant::now();
+        let disk = |free: u64, name| {
+            json!({ "mounts": [
+                { "name": "/", "total": 100, "available": free },
+                { "name": name, "total": 50, "available": free / 2 },
+            ] })
+        };
+        assert_eq!(
+            t.check("disk", &disk(40, "/data"), "T0", t0),
+            Decision::Emit
+        );
+        let later = t0 + Duration::from_secs(5);
+        assert_eq!(
+            t.check("disk", &disk(39, "/data"), "T1", later),
+            Decision::Skip
+        );
+        assert_eq!(
+            t.check("disk", &disk(39, "/srv"), "T2", later),
+            Decision::Emit
+        );
+    }
+}
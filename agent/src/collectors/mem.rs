@@ -1,5 +1,5 @@
 use serde::Serialize;
-use sysinfo::{System, SystemExt};
+use sysinfo::System;
 #[derive(Debug, Serialize, Clone)]
 pub struct MemStats {
     pub total: u64,
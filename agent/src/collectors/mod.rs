@@ -1,23 +1,38 @@
 use anyhow::Result;
 use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
-use tokio::sync::mpsc::Sender;
-use tracing::info;
+use tokio::sync::{mpsc::Sender, watch};
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 pub mod cpu;
+pub mod delta;
 pub mod disk;
 pub mod mem;
 pub mod net;
 pub mod os;
 pub mod proc;
 pub mod win_eventlog;
+use crate::alerts::{AlertEngine, AlertEvent};
 use crate::config::AgentConfig;
+use crate::health;
+use crate::metrics;
+use crate::output::Outputs;
+use crate::shutdown::Shutdown;
+use delta::{ChangeTracker, Decision};
 #[derive(Debug, Serialize, Clone)]
 pub struct TelemetryEnvelope<T: Serialize> {
     pub ts: String,
     pub event_id: String,
     pub instance_id: String,
+    /// Remote config document in effect, when one has been applied.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_version: Option<u64>,
     pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unchanged_since: Option<String>,
     pub body: T,
 }
 fn now_iso() -> String {
@@ -25,12 +40,174 @@ fn now_iso() -> String {
         .format(&Rfc3339)
         .unwrap_or_else(|_| "1970-01-01T00:00:00Z".into())
 }
+pub struct Emitter {
+    instance_id: String,
+    config_version: Option<u64>,
+    max_bytes: usize,
+    delta: ChangeTracker,
+    alerts: AlertEngine,
+    outputs: Outputs,
+    #[cfg(feature = "networking")]
+    net_tx: Option<Sender<Vec<u8>>>,
+    /// Tracks the writer threads and exporters started by the outputs.
+    shutdown: Shutdown,
+}
+impl Emitter {
+    pub fn new(
+        cfg: &AgentConfig,
+        #[cfg(feature = "networking")] net_tx: Option<Sender<Vec<u8>>>,
+        shutdown: &Shutdown,
+    ) -> Result<Self> {
+        Ok(Self {
+            instance_id: cfg.common.instance_id.clone(),
+            config_version: cfg.config_version,
+            max_bytes: cfg.common.max_event_bytes,
+            delta: ChangeTracker::new(
+                cfg.collectors.delta_kinds.clone(),
+                cfg.collectors.delta_ignore_fields.clone(),
+                Duration::from_secs(cfg.collectors.delta_heartbeat_secs),
+            ),
+            alerts: AlertEngine::new(&cfg.alerts.rules)?,
+            outputs: Outputs::build(
+                cfg,
+                #[cfg(feature = "networking")]
+                net_tx.clone(),
+                shutdown,
+            )?,
+            #[cfg(feature = "networking")]
+            net_tx,
+            shutdown: shutdown.clone(),
+        })
+    }
+    /// Switches to `new`. Everything that can fail is built before anything is
+    /// replaced, so on error the emitter keeps running with `old` unchanged.
+    pub fn apply(&mut self, old: &AgentConfig, new: &AgentConfig) -> Result<()> {
+        let alerts = if new.alerts != old.alerts {
+            Some(AlertEngine::new(&new.alerts.rules)?)
+        } else {
+            None
+        };
+        let outputs =
+            if new.output != old.output || new.networking.enabled != old.networking.enabled {
+                Some(Outputs::build(
+                    new,
+                    #[cfg(feature = "networking")]
+                    self.net_tx.clone(),
+                    &self.shutdown,
+                )?)
+            } else {
+                None
+            };
+        if let Some(alerts) = alerts {
+            self.alerts = alerts;
+        }
+        if let Some(outputs) = outputs {
+            self.outputs.flush();
+            self.outputs = outputs;
+        }
+        let (oc, nc) = (&old.collectors, &new.collectors);
+        if (
+            &nc.delta_kinds,
+            &nc.delta_ignore_fields,
+            nc.delta_heartbeat_secs,
+        ) != (
+            &oc.delta_kinds,
+            &oc.delta_ignore_fields,
+            oc.delta_heartbeat_secs,
+        ) {
+            self.delta = ChangeTracker::new(
+                nc.delta_kinds.clone(),
+                nc.delta_ignore_fields.clone(),
+                Duration::from_secs(nc.delta_heartbeat_secs),
+            );
+        }
+        self.instance_id = new.common.instance_id.clone();
+        self.config_version = new.config_version;
+        self.max_bytes = new.common.max_event_bytes;
+        Ok(())
+    }
+    pub fn emit<T: Serialize>(&mut self, kind: &str, body: &T) {
+        let Ok(value) = serde_json::to_value(body) else {
+            return;
+        };
+        let fired = self.alerts.observe(kind, &value, Instant::now());
+        metrics::observe(kind, &value);
+        health::collected(kind);
+        self.send(kind, value);
+        self.emit_alerts(fired);
+    }
+    pub fn observe_processes(&mut self, names: &HashSet<String>) {
+        let fired = self.alerts.observe_processes(names, Instant::now());
+        self.emit_alerts(fired);
+    }
+    fn emit_alerts(&mut self, fired: Vec<AlertEvent>) {
+        for ev in fired {
+            info!(rule=%ev.rule_id, state=?ev.state, series=?ev.series, "alert");
+            if let Ok(v) = serde_json::to_value(&ev) {
+                self.send("alert", v);
+            }
+        }
+    }
+    fn send(&mut self, kind: &str, body: Value) {
+        let ts = now_iso();
+        let unchanged_since = match self.delta.check(kind, &body, &ts, Instant::now()) {
+            Decision::Emit => None,
+            Decision::Heartbeat { unchanged_since } => Some(unchanged_since),
+            Decision::Skip => return,
+        };
+        let env = TelemetryEnvelope {
+            ts,
+            event_id: Uuid::new_v4().to_string(),
+            instance_id: self.instance_id.clone(),
+            config_version: self.config_version,
+            kind: kind.to_string(),
+            unchanged_since,
+            body,
+        };
+        if let Ok(mut line) = serde_json::to_vec(&env) {
+            line.push(b'\n');
+            if line.len() <= self.max_bytes {
+                debug!(event=%kind, size=line.len(), "telemetry");
+                metrics::event_emitted(kind);
+                health::record_event(&line);
+                self.outputs.dispatch(kind, &line);
+            } else {
+                metrics::event_dropped(kind, "oversize");
+            }
+        }
+    }
+}
+/// One envelope line for an event raised outside the collect loop, such as the
+/// network sender's `tls_rotated`.
+pub fn envelope_line(cfg: &AgentConfig, kind: &str, body: Value) -> Option<Vec<u8>> {
+    let env = TelemetryEnvelope {
+        ts: now_iso(),
+        event_id: Uuid::new_v4().to_string(),
+        instance_id: cfg.common.instance_id.clone(),
+        config_version: cfg.config_version,
+        kind: kind.to_string(),
+        unchanged_since: None,
+        body,
+    };
+    let mut line = serde_json::to_vec(&env).ok()?;
+    line.push(b'\n');
+    Some(line)
+}
+/// Collects until `shutdown` fires, then flushes outputs and drops them so the
+/// network sender sees its channel close.
 pub async fn run_collect_loop(
-    cfg: AgentConfig,
+    mut cfg_rx: watch::Receiver<AgentConfig>,
     #[cfg(feature = "networking")] net_tx: Option<Sender<Vec<u8>>>,
+    shutdown: Shutdown,
 ) -> Result<()> {
+    let mut cfg = cfg_rx.borrow_and_update().clone();
     let mut sys = sysinfo::System::new_all();
-    let interval = std::time::Duration::from_secs(cfg.common.interval_secs);
+    let mut out = Emitter::new(
+        &cfg,
+        #[cfg(feature = "networking")]
+        net_tx,
+        &shutdown,
+    )?;
     #[cfg(all(target_os = "windows", feature = "win-events"))]
     let _evt_rx = win_eventlog::spawn_tailer(
         cfg.collectors.win_eventlog_channels.clone(),
@@ -38,85 +215,50 @@ pub async fn run_collect_loop(
     )?;
     loop {
         sys.refresh_all();
-        let instance = cfg.common.instance_id.clone();
-        let cpu = cpu::collect(&mut sys);
-        emit(
-            &instance,
-            "cpu",
-            &cpu,
-            cfg.common.max_event_bytes,
-            #[cfg(feature = "networking")]
-            net_tx.clone(),
-        );
-        let m = mem::collect(&mut sys);
-        emit(
-            &instance,
-            "mem",
-            &m,
-            cfg.common.max_event_bytes,
-            #[cfg(feature = "networking")]
-            net_tx.clone(),
-        );
-        let d = disk::collect(&mut sys);
-        emit(
-            &instance,
-            "disk",
-            &d,
-            cfg.common.max_event_bytes,
-            #[cfg(feature = "networking")]
-            net_tx.clone(),
-        );
-        let n = net::collect(&mut sys);
-        emit(
-            &instance,
-            "net",
-            &n,
-            cfg.common.max_event_bytes,
-            #[cfg(feature = "networking")]
-            net_tx.clone(),
-        );
-        let p = proc::collect(&mut sys, cfg.collectors.top_n_procs);
-        emit(
-            &instance,
-            "proc",
-            &p,
-            cfg.common.max_event_bytes,
-            #[cfg(feature = "networking")]
-            net_tx.clone(),
-        );
-        let o = os::collect(&sys);
-        emit(
-            &instance,
-            "os",
-            &o,
-            cfg.common.max_event_bytes,
-            #[cfg(feature = "networking")]
-            net_tx.clone(),
-        );
-        tokio::time::sleep(interval).await;
-    }
-}
-fn emit<T: serde::Serialize>(
-    instance_id: &str,
-    kind: &str,
-    body: &T,
-    max_bytes: usize,
-    #[cfg(feature = "networking")] net_tx: Option<Sender<Vec<u8>>>,
-) {
-    let env = TelemetryEnvelope {
-        ts: now_iso(),
-        event_id: Uuid::new_v4().to_string(),
-        instance_id: instance_id.to_string(),
-        kind: kind.to_string(),
-        body,
-    };
-    if let Ok(mut line) = serde_json::to_vec(&env) {
-        let _ = line.push(b'\n');
-        if line.len() <= max_bytes {
-            info!(event=%kind, size=line.len(), "telemetry");
-            #[cfg(feature = "networking")]
-            if let Some(tx) = net_tx {
-                let _ = tx.try_send(line);
+        out.emit("cpu", &cpu::collect(&mut sys));
+        out.emit("mem", &mem::collect(&mut sys));
+        out.emit("disk", &disk::collect(&mut sys));
+        out.emit("net", &net::collect(&mut sys));
+        out.emit("proc", &proc::collect(&mut sys, cfg.collectors.top_n_procs));
+        out.observe_processes(&proc::names(&sys));
+        out.emit("os", &os::collect(&sys));
+        tokio::select! {
+            _ = shutdown.cancelled() => {
+                out.outputs.flush();
+                info!("collectors stopped");
+                return Ok(());
+            }
+            _ = tokio::time::sleep(Duration::from_secs(cfg.common.interval_secs)) => {}
+            Ok(()) = cfg_rx.changed() => {
+                let next = cfg_rx.borrow_and_update().clone();
+                #[cfg(feature = "networking")]
+                let spawned = next.networking.enabled && out.net_tx.is_none();
+                #[cfg(feature = "networking")]
+                if spawned {
+                    match crate::transport::modu::maybe_spawn_network_sender(
+                        &next,
+                        cfg_rx.clone(),
+                        shutdown.clone(),
+                    )
+                    .await
+                    {
+                        Ok(tx) => out.net_tx = tx,
+                        Err(e) => {
+                            warn!(error=?e, "collectors kept previous settings");
+                            continue;
+                        }
+                    }
+                }
+                if let Err(e) = out.apply(&cfg, &next) {
+                    warn!(error=?e, "collectors kept previous settings");
+                    // Closing its channel stops a sender started for this config.
+                    #[cfg(feature = "networking")]
+                    if spawned {
+                        out.net_tx = None;
+                    }
+                    continue;
+                }
+                cfg = next;
             }
         }
     }
@@ -9,13 +9,13 @@ pub struct OsStats {
     pub uptime_secs: u64,
     pub boot_time_secs: u64,
 }
-pub fn collect(sys: &System) -> OsStats {
+pub fn collect(_sys: &System) -> OsStats {
     OsStats {
-        name: sys.name(),
-        version: sys.os_version(),
-        kernel_version: sys.kernel_version(),
-        host_name: sys.host_name(),
-        uptime_secs: sys.uptime().as_secs(),
-        boot_time_secs: sys.boot_time().as_secs(),
+        name: System::name(),
+        version: System::os_version(),
+        kernel_version: System::kernel_version(),
+        host_name: System::host_name(),
+        uptime_secs: System::uptime(),
+        boot_time_secs: System::boot_time(),
     }
 }
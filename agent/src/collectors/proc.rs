@@ -1,5 +1,6 @@
 use serde::Serialize;
-use sysinfo::{ProcessExt, System};
+use std::collections::HashSet;
+use sysinfo::System;
 #[derive(Debug, Serialize, Clone)]
 pub struct ProcTop {
     pub pid: i32,
@@ -18,7 +19,7 @@ pub fn collect(sys: &mut System, top_n: usize) -> ProcStats {
         .iter()
         .map(|(pid, p)| ProcTop {
             pid: pid.as_u32() as i32,
-            name: p.name().to_string_lossy().to_string(),
+            name: p.name().to_string(),
             cpu_percent: p.cpu_usage(),
             mem_bytes: p.memory(),
         })
@@ -34,3 +35,9 @@ pub fn collect(sys: &mut System, top_n: usize) -> ProcStats {
         top: v,
     }
 }
+pub fn names(sys: &System) -> HashSet<String> {
+    sys.processes()
+        .values()
+        .map(|p| p.name().to_string())
+        .collect()
+}
@@ -0,0 +1,156 @@
+use super::AgentConfig;
+use anyhow::{anyhow, bail, Context, Result};
+use toml::{Table, Value};
+const PREFIX: &str = "REA_";
+const ALIASES: &[(&str, &str)] = &[
+    ("REA_INTERVAL_SECS", "common.interval_secs"),
+    ("REA_ENABLE_NETWORKING", "networking.enabled"),
+];
+pub fn env_overrides(
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<Vec<(String, String, Value)>> {
+    let defaults = Value::try_from(AgentConfig::default()).context("serializing defaults")?;
+    let mut vars: Vec<(String, String)> = vars
+        .into_iter()
+        .filter(|(k, _)| k.starts_with(PREFIX))
+        .collect();
+    vars.sort();
+    let mut out = Vec::new();
+    for (var, raw) in vars {
+        let key = match ALIASES.iter().find(|(a, _)| *a == var) {
+            Some((_, key)) => key.to_string(),
+            None => match key_for(&var) {
+                Some(k) => k,
+                None => continue,
+            },
+        };
+        let value = parse_value(&var, &key, &raw, &defaults)?;
+        out.push((var, key, value));
+    }
+    Ok(out)
+}
+fn key_for(var: &str) -> Option<String> {
+    let rest = var.strip_prefix(PREFIX)?;
+    if !rest.contains("__") {
+        return None;
+    }
+    Some(
+        rest.split("__")
+            .map(str::to_ascii_lowercase)
+            .collect::<Vec<_>>()
+            .join("."),
+    )
+}
+fn lookup<'a>(v: &'a Value, key: &str) -> Option<&'a Value> {
+    key.split('.').try_fold(v, |v, part| v.get(part))
+}
+fn parse_value(var: &str, key: &str, raw: &str, defaults: &Value) -> Result<Value> {
+    let value = match lookup(defaults, key) {
+        Some(Value::Table(_)) => bail!("{var}: `{key}` is a section, set one of its keys instead"),
+        Some(Value::Integer(_)) => Value::Integer(
+            raw.trim()
+                .parse()
+                .map_err(|_| anyhow!("{var}: expected an integer for `{key}`, got `{raw}`"))?,
+        ),
+        Some(Value::Float(_)) => Value::Float(
+            raw.trim()
+                .parse()
+                .map_err(|_| anyhow!("{var}: expected a number for `{key}`, got `{raw}`"))?,
+        ),
+        Some(Value::Boolean(_)) => {
+            Value::Boolean(parse_bool(raw).ok_or_else(|| {
+                anyhow!("{var}: expected true/false/1/0 for `{key}`, got `{raw}`")
+            })?)
+        }
+        Some(Value::Array(items)) => parse_list(var, key, raw, items.first())?,
+        Some(_) => Value::String(raw.to_string()),
+        None => match literal(raw) {
+            Some(v) if check(key, &v).is_ok() => v,
+            _ => Value::String(raw.to_string()),
+        },
+    };
+    check(key, &value).map_err(|e| anyhow!("{var}: invalid value for `{key}`: {e}"))?;
+    Ok(value)
+}
+fn parse_bool(raw: &str) -> Option<bool> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+fn parse_list(var: &str, key: &str, raw: &str, sample: Option<&Value>) -> Result<Value> {
+    if raw.trim_start().starts_with('[') {
+        return literal(raw)
+            .ok_or_else(|| anyhow!("{var}: `{raw}` is not a valid TOML array for `{key}`"));
+    }
+    let items = raw
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| match sample {
+            Some(Value::Integer(_)) => s
+                .parse()
+                .map(Value::Integer)
+                .map_err(|_| anyhow!("{var}: list item `{s}` for `{key}` is not an integer")),
+            _ => Ok(Value::String(s.to_string())),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Value::Array(items))
+}
+fn literal(raw: &str) -> Option<Value> {
+    let t: Table = toml::from_str(&format!("v = {raw}")).ok()?;
+    t.get("v").cloned()
+}
+fn check(key: &str, value: &Value) -> Result<()> {
+    let layer = super::layers::nested(key, value.clone());
+    let cfg: AgentConfig = layer.try_into()?;
+    let round_trip = Value::try_from(&cfg)?;
+    if lookup(&round_trip, key).is_none() {
+        bail!("unknown config key");
+    }
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn vars(v: &[(&str, &str)]) -> Vec<(String, String)> {
+        v.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+    #[test]
+    fn nested_keys_are_typed_from_defaults() {
+        let out = env_overrides(vars(&[
+            ("REA_NETWORKING__ENDPOINT", "https://rx.example/ingest"),
+            ("REA_COLLECTORS__TOP_N_PROCS", "12"),
+            ("REA_COLLECTORS__WIN_EVENTLOG_CHANNELS", "System, Security"),
+            ("REA_STATUS__PORT", "9100"),
+            ("REA_NETWORKING__CA_CERT", "/etc/rea/ca.crt"),
+            ("REA_CONFIG", "/etc/rea/agent.toml"),
+            ("PATH", "/usr/bin"),
+        ]))
+        .unwrap();
+        let get = |k: &str| out.iter().find(|(_, key, _)| key == k).unwrap().2.clone();
+        assert_eq!(get("collectors.top_n_procs"), Value::Integer(12));
+        assert_eq!(get("status.port"), Value::Integer(9100));
+        assert_eq!(
+            get("collectors.win_eventlog_channels"),
+            Value::Array(vec!["System".into(), "Security".into()])
+        );
+        assert_eq!(
+            get("networking.ca_cert"),
+            Value::String("/etc/rea/ca.crt".into())
+        );
+        assert_eq!(out.len(), 5);
+    }
+    #[test]
+    fn errors_name_the_variable() {
+        let err = env_overrides(vars(&[("REA_COLLECTORS__TOP_N_PROCS", "lots")])).unwrap_err();
+        assert!(err.to_string().contains("REA_COLLECTORS__TOP_N_PROCS"));
+        let err = env_overrides(vars(&[("REA_NETWORKING__ENDPONT", "x")])).unwrap_err();
+        assert!(err.to_string().contains("REA_NETWORKING__ENDPONT"));
+        let err = env_overrides(vars(&[("REA_STATUS__PORT", "70000")])).unwrap_err();
+        assert!(err.to_string().contains("REA_STATUS__PORT"));
+    }
+}
@@ -0,0 +1,269 @@
+use super::env::env_overrides;
+use super::remote::{read_cache, RemoteDocument};
+use super::{AgentConfig, CliOverrides};
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+use toml::{Table, Value};
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    DropIn(PathBuf),
+    Env(String),
+    Cli(String),
+    Remote(u64),
+}
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(p) => write!(f, "file {}", p.display()),
+            Source::DropIn(p) => write!(f, "drop-in {}", p.display()),
+            Source::Env(v) => write!(f, "env {v}"),
+            Source::Cli(a) => write!(f, "cli {a}"),
+            Source::Remote(v) => write!(f, "remote v{v}"),
+        }
+    }
+}
+pub struct Layered {
+    pub config: AgentConfig,
+    pub sources: BTreeMap<String, Source>,
+    /// Problems that did not stop loading, for the caller to log once tracing is up.
+    pub warnings: Vec<String>,
+}
+pub fn load_layered(path: Option<&Path>, optional: bool, cli: &CliOverrides) -> Result<Layered> {
+    load_with_remote(path, optional, cli, None)
+}
+/// [`load_layered`] with `candidate` in place of the cached remote document, which
+/// sits between the drop-ins and the environment when `remote_config` is enabled.
+pub fn load_with_remote(
+    path: Option<&Path>,
+    optional: bool,
+    cli: &CliOverrides,
+    candidate: Option<&RemoteDocument>,
+) -> Result<Layered> {
+    let defaults = Value::try_from(AgentConfig::default()).context("serializing defaults")?;
+    let mut local = Vec::new();
+    let env_path = env::var_os("REA_CONFIG").map(PathBuf::from);
+    if let Some(p) = path.or(env_path.as_deref()) {
+        if !p.exists() && !optional {
+            bail!(
+                "config file {} (from --config / REA_CONFIG) does not exist; \
+                 pass --config-optional to start from defaults instead",
+                p.display()
+            );
+        }
+        if p.exists() {
+            local.push((read_layer(p)?, Source::File(p.into())));
+            for d in drop_ins(p)? {
+                local.push((read_layer(&d)?, Source::DropIn(d)));
+            }
+        }
+    }
+    let vars =
+        env::vars_os().filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)));
+    let mut overrides = Vec::new();
+    for (var, key, value) in env_overrides(vars)? {
+        overrides.push((nested(&key, value), Source::Env(var)));
+    }
+    for (flag, key, value) in cli_layer(cli) {
+        overrides.push((nested(key, value), Source::Cli(flag.into())));
+    }
+    let mut warnings = Vec::new();
+    let remote = match candidate {
+        Some(doc) => Some(doc.clone()),
+        None => {
+            let mut trial = defaults.clone();
+            for (layer, src) in local.iter().chain(&overrides) {
+                merge_toml(&mut trial, layer.clone(), "", src, &mut BTreeMap::new());
+            }
+            let trial: AgentConfig = trial.try_into().context("building effective config")?;
+            let remote = &trial.remote_config;
+            let cached = if remote.enabled {
+                read_cache(&remote.cache_path)?
+            } else {
+                None
+            };
+            // The cache file is only as trustworthy as its signature.
+            match cached.map(|doc| doc.verify(&remote.public_key).map(|()| doc)) {
+                Some(Err(e)) => {
+                    warnings.push(format!(
+                        "ignoring cached remote config {}: {e:#}",
+                        remote.cache_path.display()
+                    ));
+                    None
+                }
+                verified => verified.transpose()?,
+            }
+        }
+    };
+    if let Some(doc) = &remote {
+        local.push((doc.layer()?, Source::Remote(doc.version)));
+    }
+    let mut merged = defaults;
+    let mut sources = BTreeMap::new();
+    record_leaves(&merged, "", &Source::Default, &mut sources);
+    for (layer, src) in local.into_iter().chain(overrides) {
+        merge_toml(&mut merged, layer, "", &src, &mut sources);
+    }
+    let mut config: AgentConfig = merged.try_into().context("building effective config")?;
+    config.config_version = remote.map(|d| d.version);
+    Ok(Layered {
+        config,
+        sources,
+        warnings,
+    })
+}
+impl Layered {
+    pub fn summary(&self) -> BTreeMap<String, String> {
+        self.sources
+            .iter()
+            .filter(|(_, s)| **s != Source::Default)
+            .map(|(k, s)| (k.clone(), s.to_string()))
+            .collect()
+    }
+}
+pub fn drop_in_dir(config_path: &Path) -> PathBuf {
+    config_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("conf.d")
+}
+fn drop_ins(config_path: &Path) -> Result<Vec<PathBuf>> {
+    let dir = drop_in_dir(config_path);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut out = Vec::new();
+    for e in fs::read_dir(&dir).with_context(|| format!("reading {}", dir.display()))? {
+        let p = e?.path();
+        if p.is_file() && p.extension().is_some_and(|x| x == "toml") {
+            out.push(p);
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+fn read_layer(p: &Path) -> Result<Value> {
+    let s =
+        fs::read_to_string(p).with_context(|| format!("reading config file {}", p.display()))?;
+    toml::from_str::<AgentConfig>(&s)
+        .with_context(|| format!("invalid config in {}", p.display()))?;
+    let t: Table = toml::from_str(&s).with_context(|| format!("parsing {}", p.display()))?;
+    Ok(Value::Table(t))
+}
+fn cli_layer(cli: &CliOverrides) -> Vec<(&'static str, &'static str, Value)> {
+    let mut out = Vec::new();
+    if cli.enable_networking {
+        out.push((
+            "--enable-networking",
+            "networking.enabled",
+            Value::Boolean(true),
+        ));
+    }
+    if let Some(p) = cli.status_port {
+        out.push(("--status-port", "status.port", Value::Integer(p.into())));
+    }
+    out
+}
+pub(super) fn nested(key: &str, value: Value) -> Value {
+    key.rsplit('.').fold(value, |acc, part| {
+        let mut t = Table::new();
+        t.insert(part.to_string(), acc);
+        Value::Table(t)
+    })
+}
+fn join(prefix: &str, k: &str) -> String {
+    if prefix.is_empty() {
+        k.to_string()
+    } else {
+        format!("{prefix}.{k}")
+    }
+}
+pub fn merge_toml(
+    base: &mut Value,
+    overlay: Value,
+    prefix: &str,
+    src: &Source,
+    sources: &mut BTreeMap<String, Source>,
+) {
+    match (base, overlay) {
+        (Value::Table(b), Value::Table(o)) => {
+            for (k, v) in o {
+                let key = join(prefix, &k);
+                match b.get_mut(&k) {
+                    Some(slot) if slot.is_table() && v.is_table() => {
+                        merge_toml(slot, v, &key, src, sources)
+                    }
+                    _ => {
+                        let nested_prefix = format!("{key}.");
+                        sources.retain(|s, _| !s.starts_with(&nested_prefix));
+                        record_leaves(&v, &key, src, sources);
+                        b.insert(k, v);
+                    }
+                }
+            }
+        }
+        (b, o) => {
+            record_leaves(&o, prefix, src, sources);
+            *b = o;
+        }
+    }
+}
+fn record_leaves(v: &Value, prefix: &str, src: &Source, sources: &mut BTreeMap<String, Source>) {
+    match v {
+        Value::Table(t) => {
+            for (k, v) in t {
+                record_leaves(v, &join(prefix, k), src, sources);
+            }
+        }
+        _ => {
+            sources.insert(prefix.to_string(), src.clone());
+        }
+    }
+}
+pub fn render_effective(l: &Layered) -> Result<String> {
+    let Value::Table(root) = Value::try_from(&l.config).context("serializing config")? else {
+        anyhow::bail!("config did not serialize to a table");
+    };
+    let mut out = String::new();
+    for (section, body) in root {
+        match body {
+            Value::Table(t) => {
+                out.push_str(&format!("[{section}]\n"));
+                render_table(&mut out, &section, "", &t, &l.sources);
+                out.push('\n');
+            }
+            other => render_line(&mut out, &section, &section, &other, &l.sources),
+        }
+    }
+    Ok(out)
+}
+fn render_table(
+    out: &mut String,
+    section: &str,
+    rel: &str,
+    t: &Table,
+    sources: &BTreeMap<String, Source>,
+) {
+    for (k, v) in t {
+        let rel_key = join(rel, k);
+        match v {
+            Value::Table(inner) => render_table(out, section, &rel_key, inner, sources),
+            _ => render_line(out, &rel_key, &join(section, &rel_key), v, sources),
+        }
+    }
+}
+fn render_line(
+    out: &mut String,
+    key: &str,
+    full: &str,
+    v: &Value,
+    sources: &BTreeMap<String, Source>,
+) {
+    let src = sources.get(full).cloned().unwrap_or(Source::Default);
+    out.push_str(&format!("{key} = {v}  # {src}\n"));
+}
@@ -0,0 +1,149 @@
+use super::{load_with_remote, validate, AgentConfig, CliOverrides};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::Path};
+use toml::{Table, Value};
+/// Signed config document served at `/config/<instance_id>`; cached as-is once accepted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteDocument {
+    pub version: u64,
+    /// TOML in the same format as a local config file.
+    pub config: String,
+    /// Base64 Ed25519 signature over [`RemoteDocument::signed_bytes`].
+    pub signature: String,
+}
+impl RemoteDocument {
+    /// Version and body together, so an old body cannot be replayed under a newer version.
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        format!("{}\n{}", self.version, self.config).into_bytes()
+    }
+    pub fn verify(&self, public_key: &str) -> Result<()> {
+        let key = crate::update::parse_public_key(public_key)?;
+        crate::update::verify_signature(&key, &self.signed_bytes(), &self.signature)
+    }
+    /// The document as a config layer. It may not change the keys that decide
+    /// which documents are trusted.
+    pub fn layer(&self) -> Result<Value> {
+        toml::from_str::<AgentConfig>(&self.config)
+            .with_context(|| format!("invalid config in remote version {}", self.version))?;
+        let t: Table = toml::from_str(&self.config)
+            .with_context(|| format!("parsing remote version {}", self.version))?;
+        if t.contains_key("remote_config") {
+            bail!(
+                "remote version {}: `remote_config` can only be set locally",
+                self.version
+            );
+        }
+        if t.get("update").and_then(|u| u.get("public_key")).is_some() {
+            bail!(
+                "remote version {}: `update.public_key` can only be set locally",
+                self.version
+            );
+        }
+        Ok(Value::Table(t))
+    }
+}
+pub fn read_cache(path: &Path) -> Result<Option<RemoteDocument>> {
+    match fs::read(path) {
+        Ok(b) => Ok(Some(
+            serde_json::from_slice(&b).with_context(|| format!("parsing {}", path.display()))?,
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+    }
+}
+fn write_cache(path: &Path, doc: &RemoteDocument) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec(doc)?)
+        .with_context(|| format!("write {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("write {}", path.display()))
+}
+/// Checks `doc` against the running config, then layers and validates it exactly
+/// like a local config change; on success it is cached for the reloader to pick up.
+pub fn accept(
+    doc: &RemoteDocument,
+    current: &AgentConfig,
+    path: Option<&Path>,
+    optional: bool,
+    overrides: &CliOverrides,
+) -> Result<AgentConfig> {
+    let applied = current.config_version.unwrap_or(0);
+    if doc.version <= applied {
+        bail!(
+            "version {} is not newer than applied version {applied}",
+            doc.version
+        );
+    }
+    doc.verify(&current.remote_config.public_key)?;
+    let next = load_with_remote(path, optional, overrides, Some(doc))?.config;
+    validate(&next)?;
+    write_cache(&current.remote_config.cache_path, doc)?;
+    Ok(next)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{load_layered, Source};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use ed25519_dalek::{Signer, SigningKey};
+    fn signed(key: &SigningKey, version: u64, config: &str) -> RemoteDocument {
+        let mut doc = RemoteDocument {
+            version,
+            config: config.into(),
+            signature: String::new(),
+        };
+        doc.signature = STANDARD.encode(key.sign(&doc.signed_bytes()).to_bytes());
+        doc
+    }
+    #[test]
+    fn accepted_documents_layer_between_drop_ins_and_env() {
+        let dir = std::env::temp_dir().join(format!("rea-remote-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let key = SigningKey::from_bytes(&[3; 32]);
+        let file = dir.join("agent.toml");
+        fs::write(
+            &file,
+            format!(
+                "[common]\ninterval_secs = 5\n[remote_config]\nenabled = true\n\
+                 public_key = \"{}\"\ncache_path = {:?}\n",
+                STANDARD.encode(key.verifying_key().as_bytes()),
+                dir.join("remote.json"),
+            ),
+        )
+        .unwrap();
+        let overrides = CliOverrides::default();
+        let current = load_layered(Some(&file), false, &overrides).unwrap().config;
+        assert_eq!(current.config_version, None);
+        let doc = signed(&key, 2, "[common]\ninterval_secs = 30\n");
+        let next = accept(&doc, &current, Some(&file), false, &overrides).unwrap();
+        assert_eq!(next.common.interval_secs, 30);
+        assert_eq!(next.config_version, Some(2));
+        let reloaded = load_layered(Some(&file), false, &overrides).unwrap();
+        assert_eq!(reloaded.config, next);
+        assert_eq!(reloaded.sources["common.interval_secs"], Source::Remote(2));
+        let mut tampered = signed(&key, 3, "[common]\ninterval_secs = 30\n");
+        tampered.config = "[common]\ninterval_secs = 1\n".into();
+        let rejected = [
+            signed(&key, 2, "[common]\ninterval_secs = 10\n"),
+            tampered,
+            signed(&SigningKey::from_bytes(&[4; 32]), 3, ""),
+            signed(&key, 3, "[common]\ninterval_secs = 0\n"),
+            signed(&key, 3, "[remote_config]\nenabled = false\n"),
+            signed(&key, 3, "[update]\npublic_key = \"\"\n"),
+        ];
+        for doc in rejected {
+            assert!(accept(&doc, &next, Some(&file), false, &overrides).is_err());
+        }
+        assert_eq!(read_cache(&dir.join("remote.json")).unwrap(), Some(doc));
+        let mut forged = signed(&key, 9, "[common]\ninterval_secs = 30\n");
+        forged.config = "[common]\ninterval_secs = 1\n".into();
+        write_cache(&dir.join("remote.json"), &forged).unwrap();
+        let restarted = load_layered(Some(&file), false, &overrides).unwrap();
+        assert_eq!(restarted.config, current);
+        assert!(restarted.warnings[0].contains("ignoring cached remote config"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
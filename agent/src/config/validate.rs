@@ -0,0 +1,347 @@
+use super::{non_empty, resolve_secret, AgentConfig, OutputMode, SinkType, SyslogTransport};
+use crate::alerts::AlertEngine;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::PathBuf;
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub path: String,
+    pub message: String,
+}
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+#[derive(Debug)]
+pub struct ValidationErrors(pub Vec<Problem>);
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} config error(s)", self.0.len())?;
+        for p in &self.0 {
+            write!(f, "\n  - {p}")?;
+        }
+        Ok(())
+    }
+}
+impl std::error::Error for ValidationErrors {}
+#[derive(Default)]
+struct Checker(Vec<Problem>);
+impl Checker {
+    fn check(&mut self, ok: bool, path: &str, message: &str) {
+        if !ok {
+            self.push(path, message.to_string());
+        }
+    }
+    fn push(&mut self, path: &str, message: String) {
+        self.0.push(Problem {
+            path: path.into(),
+            message,
+        });
+    }
+    fn file(&mut self, path: &str, p: &Option<PathBuf>) {
+        if let Some(f) = non_empty(p) {
+            if !f.is_file() {
+                self.push(path, format!("file {} does not exist", f.display()));
+            }
+        }
+    }
+    fn url(&mut self, path: &str, url: &str) {
+        match url.parse::<http::Uri>() {
+            Ok(u) if matches!(u.scheme_str(), Some("https" | "http")) && u.host().is_some() => {}
+            Ok(_) => self.push(path, "must be an absolute http(s) URL".into()),
+            Err(e) => self.push(path, format!("not a valid URL: {e}")),
+        }
+    }
+}
+pub fn validate(cfg: &AgentConfig) -> Result<(), ValidationErrors> {
+    let mut c = Checker::default();
+    let common = &cfg.common;
+    c.check(
+        !common.instance_id.trim().is_empty(),
+        "common.instance_id",
+        "must not be empty",
+    );
+    c.check(
+        common.interval_secs > 0,
+        "common.interval_secs",
+        "must be greater than zero",
+    );
+    c.check(
+        common.max_event_bytes > 0,
+        "common.max_event_bytes",
+        "must be greater than zero",
+    );
+    let mut ids = HashSet::new();
+    for (i, rule) in cfg.alerts.rules.iter().enumerate() {
+        let path = format!("alerts.rules[{i}]");
+        if !ids.insert(rule.id.as_str()) {
+            c.push(
+                &format!("{path}.id"),
+                format!("duplicate rule id `{}`", rule.id),
+            );
+        } else if let Err(e) = AlertEngine::new(std::slice::from_ref(rule)) {
+            c.push(&format!("{path}.when"), format!("{e:#}"));
+        }
+    }
+    let out = &cfg.output;
+    if out.mode == OutputMode::File && out.sinks.is_empty() {
+        c.check(
+            non_empty(&out.file_path).is_some(),
+            "output.file_path",
+            "required when output.mode = \"file\"",
+        );
+        c.check(
+            out.rotate_bytes > 0,
+            "output.rotate_bytes",
+            "must be greater than zero",
+        );
+    }
+    for (i, sink) in out.sinks.iter().enumerate() {
+        let path = format!("output.sinks[{i}]");
+        match &sink.sink {
+            SinkType::File {
+                path: file,
+                rotate_bytes,
+                ..
+            } => {
+                c.check(
+                    !file.as_os_str().is_empty(),
+                    &format!("{path}.path"),
+                    "must not be empty",
+                );
+                c.check(
+                    rotate_bytes.unwrap_or(out.rotate_bytes) > 0,
+                    &format!("{path}.rotate_bytes"),
+                    "must be greater than zero",
+                );
+            }
+            SinkType::Network => c.check(
+                cfg.networking.enabled,
+                &format!("{path}.type"),
+                "network sink requires networking.enabled = true",
+            ),
+            SinkType::Syslog {
+                transport,
+                facility,
+                ca_cert,
+                ..
+            } => {
+                c.check(
+                    *facility <= 23,
+                    &format!("{path}.facility"),
+                    "must be between 0 and 23",
+                );
+                if *transport == SyslogTransport::Tls {
+                    c.check(
+                        non_empty(ca_cert).is_some(),
+                        &format!("{path}.ca_cert"),
+                        "required for transport = \"tls\"",
+                    );
+                    c.file(&format!("{path}.ca_cert"), ca_cert);
+                }
+                c.check(
+                    cfg!(unix) || *transport != SyslogTransport::Unix,
+                    &format!("{path}.transport"),
+                    "unix sockets are not available on this platform",
+                );
+            }
+            SinkType::Otlp { endpoint, .. } => c.url(&format!("{path}.endpoint"), endpoint),
+            SinkType::Stdout => {}
+        }
+        c.check(
+            sink.kinds.iter().all(|k| !k.trim().is_empty()),
+            &format!("{path}.kinds"),
+            "must not contain empty kinds",
+        );
+    }
+    if let Some(bind) = cfg.status.bind.as_deref().filter(|b| !b.is_empty()) {
+        match bind.strip_prefix("unix:") {
+            Some(path) => {
+                c.check(
+                    !path.is_empty(),
+                    "status.bind",
+                    "unix socket path must not be empty",
+                );
+                c.check(
+                    cfg!(unix),
+                    "status.bind",
+                    "unix sockets are not available on this platform",
+                );
+            }
+            None => c.check(
+                bind.parse::<std::net::SocketAddr>().is_ok(),
+                "status.bind",
+                "must be host:port (IP address) or unix:/path",
+            ),
+        }
+    }
+    c.check(
+        cfg.status.token.as_deref() != Some(""),
+        "status.token",
+        "must not be empty when set",
+    );
+    let net = &cfg.networking;
+    if net.enabled {
+        c.url("networking.endpoint", &net.endpoint);
+        let mut keys = HashSet::new();
+        for (i, e) in net.endpoints.iter().enumerate() {
+            c.url(&format!("networking.endpoints[{i}].url"), &e.url);
+            c.check(
+                keys.insert(e.key()),
+                &format!("networking.endpoints[{i}]"),
+                "duplicate endpoint; give each a distinct url or name",
+            );
+        }
+        c.check(
+            net.breaker_failures > 0,
+            "networking.breaker_failures",
+            "must be greater than zero",
+        );
+        c.check(
+            net.breaker_open_secs > 0,
+            "networking.breaker_open_secs",
+            "must be greater than zero",
+        );
+        c.check(
+            net.batch_max_events > 0,
+            "networking.batch_max_events",
+            "must be greater than zero",
+        );
+        c.check(
+            net.batch_max_bytes > 0,
+            "networking.batch_max_bytes",
+            "must be greater than zero",
+        );
+        c.check(
+            net.flush_interval_ms > 0,
+            "networking.flush_interval_ms",
+            "must be greater than zero",
+        );
+        c.check(
+            net.queue_max_bytes > 0,
+            "networking.queue_max_bytes",
+            "must be greater than zero",
+        );
+        c.check(
+            net.retry_budget > 0,
+            "networking.retry_budget",
+            "must be greater than zero",
+        );
+        c.file("networking.ca_cert", &net.ca_cert);
+        c.file("networking.client_cert", &net.client_cert);
+        c.file("networking.client_key", &net.client_key);
+        c.file("networking.client_pkcs12", &net.client_pkcs12);
+        c.check(
+            non_empty(&net.client_pkcs12).is_none() || non_empty(&net.client_cert).is_none(),
+            "networking.client_pkcs12",
+            "set either client_pkcs12 or client_cert/client_key, not both",
+        );
+        if let Some(reference) = net.key_password.as_deref().filter(|r| !r.is_empty()) {
+            if let Err(e) = resolve_secret(reference) {
+                c.push("networking.key_password", format!("{e:#}"));
+            }
+        }
+        c.check(
+            non_empty(&net.client_cert).is_some() == non_empty(&net.client_key).is_some(),
+            "networking.client_key",
+            "client_cert and client_key must be set together",
+        );
+        let proxy = &net.proxy;
+        if !proxy.url.is_empty() && proxy.url != "direct" {
+            match proxy.url.parse::<http::Uri>() {
+                Ok(u)
+                    if matches!(
+                        u.scheme_str(),
+                        Some("http" | "https" | "socks5" | "socks5h")
+                    ) && u.host().is_some() => {}
+                _ => c.push(
+                    "networking.proxy.url",
+                    "must be an http://, https://, socks5:// or socks5h:// URL, or `direct`".into(),
+                ),
+            }
+        }
+        if let Some(reference) = proxy.password.as_deref().filter(|r| !r.is_empty()) {
+            c.check(
+                !proxy.username.is_empty(),
+                "networking.proxy.password",
+                "needs networking.proxy.username",
+            );
+            if let Err(e) = resolve_secret(reference) {
+                c.push("networking.proxy.password", format!("{e:#}"));
+            }
+        }
+        if let Some(pin) = net.spki_pin_sha256.as_deref().filter(|p| !p.is_empty()) {
+            let hex = pin.len() == 64 && pin.bytes().all(|b| b.is_ascii_hexdigit());
+            let b64 = pin.len() == 44 && pin.ends_with('=');
+            c.check(
+                hex || b64,
+                "networking.spki_pin_sha256",
+                "must be a SHA-256 digest (64 hex chars or base64)",
+            );
+        }
+    }
+    let update = &cfg.update;
+    if !update.manifest_url.is_empty() {
+        c.url("update.manifest_url", &update.manifest_url);
+    }
+    if !update.public_key.is_empty() {
+        c.check(
+            crate::update::parse_public_key(&update.public_key).is_ok(),
+            "update.public_key",
+            "must be a base64-encoded 32-byte Ed25519 public key",
+        );
+    }
+    c.check(
+        update.health_timeout_secs > 0,
+        "update.health_timeout_secs",
+        "must be greater than zero",
+    );
+    let remote = &cfg.remote_config;
+    if remote.enabled {
+        if !remote.url.is_empty() {
+            c.url("remote_config.url", &remote.url);
+        }
+        c.check(
+            crate::update::parse_public_key(&remote.public_key).is_ok(),
+            "remote_config.public_key",
+            "must be a base64-encoded 32-byte Ed25519 public key",
+        );
+        c.check(
+            remote
+                .group
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+                && !remote.group.starts_with('.'),
+            "remote_config.group",
+            "may only contain letters, digits, '-', '_' and '.'",
+        );
+        c.check(
+            remote.poll_secs > 0,
+            "remote_config.poll_secs",
+            "must be greater than zero",
+        );
+    }
+    let enroll = &cfg.enrollment;
+    if enroll.enabled {
+        if !enroll.url.is_empty() {
+            c.url("enrollment.url", &enroll.url);
+        }
+        c.check(
+            non_empty(&net.client_cert).is_none() && non_empty(&net.client_pkcs12).is_none(),
+            "enrollment.enabled",
+            "conflicts with networking.client_cert and client_pkcs12; \
+             the enrolled certificate is used instead",
+        );
+        c.check(
+            enroll.renew_before_days > 0,
+            "enrollment.renew_before_days",
+            "must be greater than zero",
+        );
+    }
+    if c.0.is_empty() {
+        Ok(())
+    } else {
+        Err(ValidationErrors(c.0))
+    }
+}
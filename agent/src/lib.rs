@@ -1,13 +1,80 @@
+pub mod alerts;
+pub mod app;
 pub mod collectors;
 pub mod config;
+pub mod health;
 pub mod logging;
+pub mod metrics;
+pub mod output;
+pub mod reload;
 pub mod service;
+pub mod shutdown;
+#[cfg(all(test, feature = "networking"))]
+mod test_support;
 pub mod transport;
+pub mod update;
 #[cfg(test)]
 mod tests {
-    use super::config::merge_config;
+    use super::config::{layers::merge_toml, load_layered, validate, AgentConfig, Source};
+    use anyhow::Result;
     use proptest::prelude::*;
+    use std::fs;
+    fn merge_config(a: &AgentConfig, b: &AgentConfig) -> Result<AgentConfig> {
+        let mut base = toml::Value::try_from(a)?;
+        let overlay = toml::Value::try_from(b)?;
+        merge_toml(
+            &mut base,
+            overlay,
+            "",
+            &Source::Default,
+            &mut Default::default(),
+        );
+        Ok(base.try_into()?)
+    }
     proptest! {
-    #[test] fn merge_config_prefers_b_over_a(s in ".*"){ let a=super::config::AgentConfig::default(); let mut b=a.clone(); b.common.instance_id=s.clone(); let m=merge_config(&a,&b); prop_assert_eq!(m.common.instance_id,s); } }
+    #[test] fn merge_config_prefers_b_over_a(s in ".*"){ let a=super::config::AgentConfig::default(); let mut b=a.clone(); b.common.instance_id=s.clone(); let m=merge_config(&a,&b).unwrap(); prop_assert_eq!(m.common.instance_id,s); } }
+    #[test]
+    fn partial_files_and_drop_ins_merge_per_field() {
+        let dir = std::env::temp_dir().join(format!("rea-cfg-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        let file = dir.join("agent.toml");
+        fs::write(&file, "[networking]\nenabled = true\n").unwrap();
+        fs::write(
+            dir.join("conf.d").join("10-endpoint.toml"),
+            "[networking]\nendpoint = \"https://example.test/ingest\"\n",
+        )
+        .unwrap();
+        let l = load_layered(Some(&file), false, &Default::default()).unwrap();
+        assert!(l.config.networking.enabled);
+        assert_eq!(l.config.networking.endpoint, "https://example.test/ingest");
+        assert_eq!(l.config.networking.retry_budget, 8);
+        assert_eq!(l.sources["networking.enabled"], Source::File(file.clone()));
+        assert_eq!(
+            l.sources["networking.endpoint"],
+            Source::DropIn(dir.join("conf.d").join("10-endpoint.toml"))
+        );
+        assert_eq!(l.sources["networking.retry_budget"], Source::Default);
+        fs::remove_dir_all(&dir).ok();
+    }
+    #[test]
+    fn validation_reports_every_problem_with_its_path() {
+        let mut cfg = super::config::AgentConfig::default();
+        cfg.common.interval_secs = 0;
+        cfg.networking.enabled = true;
+        cfg.networking.endpoint = "not a url".into();
+        cfg.networking.ca_cert = Some("/nonexistent/ca.crt".into());
+        let errs = validate(&cfg).unwrap_err();
+        let paths: Vec<_> = errs.0.iter().map(|p| p.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "common.interval_secs",
+                "networking.endpoint",
+                "networking.ca_cert"
+            ]
+        );
+        let bad = toml::from_str::<super::config::AgentConfig>("[output]\nmode = \"jsonl\"\n");
+        assert!(bad.is_err());
+    }
 }
 // touch
@@ -1,36 +1,98 @@
-use crate::config::AgentConfig;
-use anyhow::Result;
+use crate::config::{non_empty, AgentConfig, Output};
+use anyhow::{Context, Result};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
 use std::{fs, path::Path};
-pub fn init_tracing(cfg: &AgentConfig) -> Result<()> {
-    let env_filter = std::env::var("RUST_LOG").unwrap_or_else(|_| "info,agent=info".into());
-    if cfg.output.mode == "file" {
-        if let Some(path) = &cfg.output.file_path {
-            if let Some(dir) = path.parent() {
-                fs::create_dir_all(dir)?;
-            }
-            let file_appender = tracing_appender::rolling::never(
-                path.parent().unwrap_or(Path::new(".")),
-                path.file_name().unwrap(),
-            );
-            let (nb, guard) = tracing_appender::non_blocking(file_appender);
-            Box::leak(Box::new(guard));
-            tracing_subscriber::fmt()
-                .with_env_filter(env_filter)
-                .with_writer(nb)
-                .json()
-                .flatten_event(true)
-                .init();
-        } else {
-            tracing_subscriber::fmt()
-                .with_env_filter(env_filter)
-                .json()
-                .init();
+use tokio::sync::watch;
+use tracing::{info, warn};
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_subscriber::fmt::MakeWriter;
+enum Target {
+    Stderr,
+    /// The guard flushes the file writer when the target is replaced.
+    File(NonBlocking, #[allow(dead_code)] WorkerGuard),
+}
+pub enum LogWriter {
+    Stderr(io::Stderr),
+    File(NonBlocking),
+}
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            LogWriter::Stderr(w) => w.write(buf),
+            LogWriter::File(w) => w.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            LogWriter::Stderr(w) => w.flush(),
+            LogWriter::File(w) => w.flush(),
+        }
+    }
+}
+#[derive(Clone)]
+pub struct LogHandle {
+    target: Arc<Mutex<Target>>,
+}
+impl<'a> MakeWriter<'a> for LogHandle {
+    type Writer = LogWriter;
+    fn make_writer(&'a self) -> LogWriter {
+        match &*self.target.lock().unwrap_or_else(|e| e.into_inner()) {
+            Target::Stderr => LogWriter::Stderr(io::stderr()),
+            Target::File(nb, _) => LogWriter::File(nb.clone()),
         }
-    } else {
-        tracing_subscriber::fmt()
-            .with_env_filter(env_filter)
-            .json()
-            .init();
     }
-    Ok(())
+}
+impl LogHandle {
+    pub fn apply(&self, out: &Output) -> Result<()> {
+        let next = open_target(out)?;
+        *self.target.lock().unwrap_or_else(|e| e.into_inner()) = next;
+        Ok(())
+    }
+    pub fn follow(self, mut rx: watch::Receiver<AgentConfig>) {
+        tokio::spawn(async move {
+            let mut last = rx.borrow().output.clone();
+            while rx.changed().await.is_ok() {
+                let out = rx.borrow_and_update().output.clone();
+                if out.log_file == last.log_file {
+                    continue;
+                }
+                match self.apply(&out) {
+                    Ok(()) => {
+                        info!(log_file=?out.log_file, "log output switched");
+                        last = out;
+                    }
+                    Err(e) => warn!(error=?e, "keeping previous log output"),
+                }
+            }
+        });
+    }
+}
+fn open_target(out: &Output) -> Result<Target> {
+    let Some(path) = non_empty(&out.log_file) else {
+        return Ok(Target::Stderr);
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file_appender = tracing_appender::rolling::never(
+        path.parent().unwrap_or(Path::new(".")),
+        path.file_name()
+            .context("output.log_file has no file name")?,
+    );
+    let (nb, guard) = tracing_appender::non_blocking(file_appender);
+    Ok(Target::File(nb, guard))
+}
+pub fn init_tracing(cfg: &AgentConfig) -> Result<LogHandle> {
+    let env_filter = std::env::var("RUST_LOG").unwrap_or_else(|_| "info,agent=info".into());
+    let handle = LogHandle {
+        target: Arc::new(Mutex::new(open_target(&cfg.output)?)),
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(env_filter)
+        .with_writer(handle.clone())
+        .json()
+        .flatten_event(true)
+        .init();
+    Ok(handle)
 }
@@ -1,19 +1,34 @@
 use anyhow::Result;
 use clap::{ArgAction, Parser, Subcommand};
 use std::path::PathBuf;
+use tracing::{info, warn};
+mod alerts;
+mod app;
 mod collectors;
 mod config;
+mod health;
 mod logging;
+mod metrics;
+mod output;
+mod reload;
 mod service;
+mod shutdown;
+#[cfg(all(test, feature = "networking"))]
+mod test_support;
 mod transport;
-use crate::collectors::run_collect_loop;
-use crate::config::load_config_with_precedence;
+#[cfg_attr(not(feature = "networking"), allow(dead_code))]
+mod update;
+use crate::app::RunOptions;
+use crate::config::{load_layered, render_effective, validate, CliOverrides, Source};
 use crate::logging::init_tracing;
+use crate::shutdown::Shutdown;
 #[derive(Parser, Debug)]
 #[command(name = "agent", version, about = "Rust Endpoint Agent (2025)")]
 struct Cli {
     #[arg(short, long, env = "REA_CONFIG")]
     config: Option<PathBuf>,
+    #[arg(long, env = "REA_CONFIG_OPTIONAL", action = ArgAction::SetTrue)]
+    config_optional: bool,
     #[arg(long,action=ArgAction::SetTrue)]
     enable_networking: bool,
     #[arg(long)]
@@ -28,6 +43,30 @@ enum Commands {
         #[command(subcommand)]
         cmd: ServiceCmd,
     },
+    Config {
+        #[command(subcommand)]
+        cmd: ConfigCmd,
+    },
+    /// Fetch, verify and install a newer signed release from the server.
+    #[cfg(feature = "networking")]
+    Update {
+        /// Only report whether a newer release is available.
+        #[arg(long, action = ArgAction::SetTrue)]
+        check: bool,
+        /// Print the version and exit; run against the new binary after a swap.
+        #[arg(long, action = ArgAction::SetTrue, hide = true)]
+        self_test: bool,
+    },
+}
+#[derive(Subcommand, Debug)]
+enum ConfigCmd {
+    Show {
+        #[arg(long, action = ArgAction::SetTrue)]
+        effective: bool,
+    },
+    Validate {
+        file: PathBuf,
+    },
 }
 #[derive(Subcommand, Debug)]
 enum ServiceCmd {
@@ -38,62 +77,179 @@ enum ServiceCmd {
         description: String,
         #[arg(long)]
         config: PathBuf,
+        /// Account the systemd unit runs as (Linux).
+        #[arg(long, default_value = "rea-agent")]
+        user: String,
+        /// Install below this prefix instead of `/` (Linux).
+        #[arg(long, default_value = "/")]
+        root: PathBuf,
+        /// Write files only; print the systemctl commands instead of running them (Linux).
+        #[arg(long, action = ArgAction::SetTrue)]
+        dry_run: bool,
+        #[command(flatten)]
+        windows: service::spec::InstallOptions,
+    },
+    Uninstall {
+        #[arg(long, default_value = "/")]
+        root: PathBuf,
+        #[arg(long, action = ArgAction::SetTrue)]
+        dry_run: bool,
     },
-    Uninstall,
+    Status {
+        #[arg(long, default_value = "/")]
+        root: PathBuf,
+    },
+    /// Entry point for the Windows service control manager.
+    #[command(hide = true)]
+    Run,
 }
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
-    let mut cfg = load_config_with_precedence(cli.config.as_ref())?;
-    if cli.enable_networking {
-        cfg.networking.enabled = true;
+    let overrides = CliOverrides {
+        enable_networking: cli.enable_networking,
+        status_port: cli.status_port,
+    };
+    if let Some(Commands::Config {
+        cmd: ConfigCmd::Validate { file },
+    }) = &cli.command
+    {
+        let layered = load_layered(Some(file.as_path()), false, &overrides)?;
+        validate(&layered.config)?;
+        println!("{}: ok", file.display());
+        return Ok(());
+    }
+    let layered = load_layered(cli.config.as_deref(), cli.config_optional, &overrides)?;
+    if let Some(Commands::Config {
+        cmd: ConfigCmd::Show { effective },
+    }) = &cli.command
+    {
+        if *effective {
+            print!("{}", render_effective(&layered)?);
+        } else {
+            print!("{}", toml::to_string_pretty(&layered.config)?);
+        }
+        return Ok(());
     }
-    if let Some(p) = cli.status_port {
-        cfg.status.port = Some(p);
+    validate(&layered.config)?;
+    let log = init_tracing(&layered.config)?;
+    info!(
+        path = ?cli.config,
+        from_defaults = layered.sources.values().filter(|s| **s == Source::Default).count(),
+        overrides = ?layered.summary(),
+        "config loaded"
+    );
+    for w in &layered.warnings {
+        warn!("{w}");
     }
-    init_tracing(&cfg)?;
+    let cfg = layered.config;
+    let run_opts = RunOptions {
+        config: cli.config.clone(),
+        config_optional: cli.config_optional,
+        overrides,
+    };
     match cli.command.unwrap_or(Commands::Run) {
         Commands::Run => {
-            #[cfg(feature = "status")]
-            if let Some(port) = cfg.status.port {
-                crate::transport::status::spawn_status_server(port)?;
+            let shutdown = Shutdown::default();
+            shutdown.on_signals();
+            app::run(cfg, run_opts, log, shutdown).await?;
+        }
+        Commands::Config { .. } => unreachable!("handled before tracing init"),
+        #[cfg(feature = "networking")]
+        Commands::Update {
+            self_test: true, ..
+        } => println!("{}", update::CURRENT_VERSION),
+        #[cfg(feature = "networking")]
+        Commands::Update { check, .. } => {
+            let mut args: Vec<std::ffi::OsString> = Vec::new();
+            if let Some(path) = &cli.config {
+                args.extend(["--config".into(), path.into()]);
+            }
+            if cli.config_optional {
+                args.push("--config-optional".into());
+            }
+            args.extend(["update".into(), "--self-test".into()]);
+            let hc = update::HealthCheck {
+                args,
+                timeout: std::time::Duration::from_secs(cfg.update.health_timeout_secs),
+            };
+            let exe = std::env::current_exe()?;
+            match update::run(&cfg, &exe, &hc, check).await? {
+                update::Outcome::UpToDate { version } => println!("Up to date ({version})."),
+                update::Outcome::Available { version } => {
+                    println!("Update available: {} -> {version}", update::CURRENT_VERSION)
+                }
+                update::Outcome::Updated { from, to } => {
+                    println!("Updated {from} -> {to}; restart the agent or service to run it.")
+                }
             }
-            #[cfg(feature = "networking")]
-            let net_tx = crate::transport::modu::maybe_spawn_network_sender(&cfg).await?;
-            run_collect_loop(
-                cfg,
-                #[cfg(feature = "networking")]
-                net_tx,
-            )
-            .await?;
         }
         Commands::Service { cmd } => match cmd {
+            #[cfg(target_os = "windows")]
             ServiceCmd::Install {
                 display_name,
                 description,
                 config,
+                windows,
+                ..
             } => {
-                #[cfg(target_os = "windows")]
-                {
-                    service::install::install_service(&display_name, &description, &config)?;
-                    println!("Service installed.");
-                }
-                #[cfg(not(target_os = "windows"))]
-                {
-                    eprintln!("Service install is Windows-only.");
-                }
+                let spec = service::spec::ServiceSpec::build(
+                    service::windows::SERVICE_NAME,
+                    &display_name,
+                    &description,
+                    &config,
+                    &windows,
+                )?;
+                service::install::install_service(&spec)?;
+                println!("Service installed.");
             }
-            ServiceCmd::Uninstall => {
-                #[cfg(target_os = "windows")]
-                {
-                    service::uninstall::uninstall_service()?;
-                    println!("Service uninstalled.");
-                }
-                #[cfg(not(target_os = "windows"))]
-                {
-                    eprintln!("Service uninstall is Windows-only.");
-                }
+            #[cfg(target_os = "linux")]
+            ServiceCmd::Install {
+                description,
+                config,
+                user,
+                root,
+                dry_run,
+                ..
+            } => {
+                let config = std::env::current_dir()?.join(config);
+                let exe = std::env::current_exe()?;
+                let unit = service::systemd::Systemd::new(&root, dry_run).install(
+                    &service::systemd::UnitOptions {
+                        description: &description,
+                        exe: &exe,
+                        config: &config,
+                        user: &user,
+                    },
+                )?;
+                println!("Service installed: {}", unit.display());
+            }
+            #[cfg(target_os = "windows")]
+            ServiceCmd::Uninstall { .. } => {
+                service::uninstall::uninstall_service()?;
+                println!("Service uninstalled.");
+            }
+            #[cfg(target_os = "linux")]
+            ServiceCmd::Uninstall { root, dry_run } => {
+                service::systemd::Systemd::new(&root, dry_run).uninstall()?;
+                println!("Service uninstalled.");
+            }
+            #[cfg(target_os = "windows")]
+            ServiceCmd::Run => {
+                let entry = service::windows::Entry {
+                    runtime: tokio::runtime::Handle::current(),
+                    agent: Box::new(move |shutdown| {
+                        Box::pin(app::run(cfg, run_opts, log, shutdown))
+                    }),
+                };
+                tokio::task::block_in_place(|| service::windows::start_dispatcher(entry))?;
+            }
+            #[cfg(target_os = "linux")]
+            ServiceCmd::Status { root } => {
+                println!("{}", service::systemd::Systemd::new(&root, false).status()?);
             }
+            #[allow(unreachable_patterns)]
+            _ => eprintln!("This service command is not supported on this platform."),
         },
     }
     Ok(())
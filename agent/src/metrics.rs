@@ -0,0 +1,336 @@
+//! Process-wide Prometheus registry fed by the emitter and the network sender and
+//! rendered by the status server. Without the `status` feature every call is a no-op.
+#![cfg_attr(not(feature = "status"), allow(unused_variables))]
+use serde_json::Value;
+use std::time::Duration;
+#[cfg(feature = "status")]
+mod registry {
+    use prometheus::core::{Collector, MetricVec, MetricVecBuilder};
+    use prometheus::{
+        Gauge, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+        IntGaugeVec, Opts, Registry,
+    };
+    use std::sync::OnceLock;
+    pub struct Metrics {
+        pub registry: Registry,
+        pub cpu_utilization: Gauge,
+        pub load_average: GaugeVec,
+        pub memory_bytes: IntGaugeVec,
+        pub disk_total_bytes: IntGaugeVec,
+        pub disk_available_bytes: IntGaugeVec,
+        pub net_bytes: IntCounterVec,
+        pub process_count: IntGauge,
+        pub uptime_seconds: IntGauge,
+        pub events_emitted: IntCounterVec,
+        pub events_dropped: IntCounterVec,
+        pub queue_depth: IntGauge,
+        pub queue_bytes: IntGauge,
+        pub queue_evicted: IntCounter,
+        pub post_duration: HistogramVec,
+        pub post_retries: IntCounter,
+    }
+    fn reg<T: prometheus::core::Collector + Clone + 'static>(r: &Registry, c: T) -> T {
+        r.register(Box::new(c.clone()))
+            .expect("metric names are unique");
+        c
+    }
+    pub fn get() -> &'static Metrics {
+        static M: OnceLock<Metrics> = OnceLock::new();
+        M.get_or_init(|| {
+            let r = Registry::new_custom(Some("rea".into()), None).expect("valid prefix");
+            let opts = |name: &str, help: &str| Opts::new(name, help);
+            let m = Metrics {
+                cpu_utilization: reg(
+                    &r,
+                    Gauge::with_opts(opts("cpu_utilization_percent", "Global CPU usage")).unwrap(),
+                ),
+                load_average: reg(
+                    &r,
+                    GaugeVec::new(opts("load_average", "System load average"), &["window"])
+                        .unwrap(),
+                ),
+                memory_bytes: reg(
+                    &r,
+                    IntGaugeVec::new(opts("memory_bytes", "Physical memory"), &["state"]).unwrap(),
+                ),
+                disk_total_bytes: reg(
+                    &r,
+                    IntGaugeVec::new(opts("disk_total_bytes", "Disk size per mount"), &["mount"])
+                        .unwrap(),
+                ),
+                disk_available_bytes: reg(
+                    &r,
+                    IntGaugeVec::new(
+                        opts("disk_available_bytes", "Free disk space per mount"),
+                        &["mount"],
+                    )
+                    .unwrap(),
+                ),
+                net_bytes: reg(
+                    &r,
+                    IntCounterVec::new(
+                        opts("network_bytes_total", "Bytes moved per interface"),
+                        &["iface", "direction"],
+                    )
+                    .unwrap(),
+                ),
+                process_count: reg(
+                    &r,
+                    IntGauge::with_opts(opts("process_count", "Running processes")).unwrap(),
+                ),
+                uptime_seconds: reg(
+                    &r,
+                    IntGauge::with_opts(opts("uptime_seconds", "System uptime")).unwrap(),
+                ),
+                events_emitted: reg(
+                    &r,
+                    IntCounterVec::new(
+                        opts("events_emitted_total", "Envelopes dispatched to outputs"),
+                        &["kind"],
+                    )
+                    .unwrap(),
+                ),
+                events_dropped: reg(
+                    &r,
+                    IntCounterVec::new(
+                        opts("events_dropped_total", "Envelopes lost before delivery"),
+                        &["kind", "reason"],
+                    )
+                    .unwrap(),
+                ),
+                queue_depth: reg(
+                    &r,
+                    IntGauge::with_opts(opts("queue_depth", "Events waiting in the disk queue"))
+                        .unwrap(),
+                ),
+                queue_bytes: reg(
+                    &r,
+                    IntGauge::with_opts(opts("queue_bytes", "Bytes waiting in the disk queue"))
+                        .unwrap(),
+                ),
+                queue_evicted: reg(
+                    &r,
+                    IntCounter::with_opts(opts(
+                        "queue_evicted_total",
+                        "Queued items evicted by the size cap",
+                    ))
+                    .unwrap(),
+                ),
+                post_duration: reg(
+                    &r,
+                    HistogramVec::new(
+                        HistogramOpts::new("post_duration_seconds", "Ingest POST latency"),
+                        &["outcome"],
+                    )
+                    .unwrap(),
+                ),
+                post_retries: reg(
+                    &r,
+                    IntCounter::with_opts(opts(
+                        "post_retries_total",
+                        "Failed POSTs that will be retried",
+                    ))
+                    .unwrap(),
+                ),
+                registry: r,
+            };
+            let up = reg(
+                &m.registry,
+                IntGauge::with_opts(opts("up", "Agent is running")).unwrap(),
+            );
+            up.set(1);
+            m
+        })
+    }
+    /// Removes the series of `vec` whose label values (in `names` order) are not
+    /// in `live`, leaving the others untouched.
+    pub fn retain<P: MetricVecBuilder>(vec: &MetricVec<P>, names: &[&str], live: &[Vec<&str>]) {
+        for family in vec.collect() {
+            for metric in family.get_metric() {
+                let values: Vec<&str> = names
+                    .iter()
+                    .filter_map(|n| {
+                        let pair = metric.get_label().iter().find(|l| l.get_name() == *n)?;
+                        Some(pair.get_value())
+                    })
+                    .collect();
+                if !live.contains(&values) {
+                    let _ = vec.remove_label_values(&values);
+                }
+            }
+        }
+    }
+}
+/// Updates the collector gauges from a `cpu`, `mem`, `disk`, `net`, `proc` or `os` body.
+pub fn observe(kind: &str, body: &Value) {
+    #[cfg(feature = "status")]
+    {
+        let m = registry::get();
+        let int = |v: &Value| v.as_u64().unwrap_or(0) as i64;
+        let items = |v: &Value| v.as_array().cloned().unwrap_or_default();
+        match kind {
+            "cpu" => {
+                m.cpu_utilization
+                    .set(body["global_cpu_percent"].as_f64().unwrap_or(0.0));
+                for (field, window) in [
+                    ("load_avg_one", "1m"),
+                    ("load_avg_five", "5m"),
+                    ("load_avg_fifteen", "15m"),
+                ] {
+                    m.load_average
+                        .with_label_values(&[window])
+                        .set(body[field].as_f64().unwrap_or(0.0));
+                }
+            }
+            "mem" => {
+                for state in ["total", "used", "free"] {
+                    m.memory_bytes
+                        .with_label_values(&[state])
+                        .set(int(&body[state]));
+                }
+            }
+            // Series of unmounted disks and removed interfaces are dropped; the
+            // others are updated in place so a scrape never sees them missing.
+            "disk" => {
+                let mounts = items(&body["mounts"]);
+                let live: Vec<Vec<&str>> = mounts
+                    .iter()
+                    .map(|d| vec![d["name"].as_str().unwrap_or_default()])
+                    .collect();
+                registry::retain(&m.disk_total_bytes, &["mount"], &live);
+                registry::retain(&m.disk_available_bytes, &["mount"], &live);
+                for d in &mounts {
+                    let mount = d["name"].as_str().unwrap_or_default();
+                    m.disk_total_bytes
+                        .with_label_values(&[mount])
+                        .set(int(&d["total"]));
+                    m.disk_available_bytes
+                        .with_label_values(&[mount])
+                        .set(int(&d["available"]));
+                }
+            }
+            // The counters follow the interface totals by their increase; a total
+            // that went down (a reset interface) restarts its series.
+            "net" => {
+                let ifaces = items(&body["ifaces"]);
+                let directions = [("total_received", "rx"), ("total_transmitted", "tx")];
+                let live: Vec<Vec<&str>> = ifaces
+                    .iter()
+                    .flat_map(|i| {
+                        let iface = i["name"].as_str().unwrap_or_default();
+                        directions.iter().map(move |(_, dir)| vec![iface, *dir])
+                    })
+                    .collect();
+                registry::retain(&m.net_bytes, &["iface", "direction"], &live);
+                for i in &ifaces {
+                    let iface = i["name"].as_str().unwrap_or_default();
+                    for (field, dir) in directions {
+                        let total = i[field].as_u64().unwrap_or(0);
+                        let counter = m.net_bytes.with_label_values(&[iface, dir]);
+                        if total < counter.get() {
+                            let _ = m.net_bytes.remove_label_values(&[iface, dir]);
+                            m.net_bytes.with_label_values(&[iface, dir]).inc_by(total);
+                        } else {
+                            counter.inc_by(total - counter.get());
+                        }
+                    }
+                }
+            }
+            "proc" => m.process_count.set(int(&body["process_count"])),
+            "os" => m.uptime_seconds.set(int(&body["uptime_secs"])),
+            _ => {}
+        }
+    }
+}
+pub fn event_emitted(kind: &str) {
+    #[cfg(feature = "status")]
+    registry::get()
+        .events_emitted
+        .with_label_values(&[kind])
+        .inc();
+}
+pub fn event_dropped(kind: &str, reason: &str) {
+    #[cfg(feature = "status")]
+    registry::get()
+        .events_dropped
+        .with_label_values(&[kind, reason])
+        .inc();
+}
+pub fn queue_size(depth: u64, bytes: u64) {
+    #[cfg(feature = "status")]
+    {
+        registry::get().queue_depth.set(depth as i64);
+        registry::get().queue_bytes.set(bytes as i64);
+    }
+}
+pub fn queue_evicted(n: u64) {
+    #[cfg(feature = "status")]
+    registry::get().queue_evicted.inc_by(n);
+}
+pub fn post_finished(elapsed: Duration, ok: bool) {
+    #[cfg(feature = "status")]
+    {
+        let outcome = if ok { "ok" } else { "error" };
+        registry::get()
+            .post_duration
+            .with_label_values(&[outcome])
+            .observe(elapsed.as_secs_f64());
+        if !ok {
+            registry::get().post_retries.inc();
+        }
+    }
+}
+/// Prometheus text exposition of everything registered so far.
+#[cfg(feature = "status")]
+pub fn render() -> String {
+    let mut buf = Vec::new();
+    let families = registry::get().registry.gather();
+    if let Err(e) =
+        prometheus::Encoder::encode(&prometheus::TextEncoder::new(), &families, &mut buf)
+    {
+        tracing::warn!(error=?e, "metrics encoding failed");
+    }
+    String::from_utf8(buf).unwrap_or_default()
+}
+#[cfg(all(test, feature = "status"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    #[test]
+    fn renders_collector_and_self_metrics() {
+        observe("mem", &json!({"total": 100, "used": 60, "free": 40}));
+        observe(
+            "net",
+            &json!({"ifaces": [{"name": "lo", "total_received": 5, "total_transmitted": 7}]}),
+        );
+        observe(
+            "net",
+            &json!({"ifaces": [{"name": "lo", "total_received": 9, "total_transmitted": 7}]}),
+        );
+        observe(
+            "disk",
+            &json!({"mounts": [{"name": "/rea-gone", "total": 10, "available": 5}]}),
+        );
+        observe(
+            "disk",
+            &json!({"mounts": [{"name": "/rea-kept", "total": 10, "available": 4}]}),
+        );
+        event_emitted("mem");
+        post_finished(Duration::from_millis(20), true);
+        let text = render();
+        assert!(text.contains("rea_up 1"));
+        assert!(text.contains("rea_memory_bytes{state=\"used\"} 60"));
+        assert!(text.contains("rea_network_bytes_total{direction=\"rx\",iface=\"lo\"} 9"));
+        assert!(!text.contains("/rea-gone"));
+        assert!(text.contains("rea_disk_available_bytes{mount=\"/rea-kept\"} 4"));
+        observe(
+            "net",
+            &json!({"ifaces": [{"name": "eth9", "total_received": 3, "total_transmitted": 1}]}),
+        );
+        let text = render();
+        assert!(!text.contains("iface=\"lo\""));
+        assert!(text.contains("rea_network_bytes_total{direction=\"rx\",iface=\"eth9\"} 3"));
+        assert!(text.contains("rea_events_emitted_total{kind=\"mem\"}"));
+        assert!(text.contains("rea_post_duration_seconds_count{outcome=\"ok\"}"));
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    written: u64,
+    /// Number of the next rotated file, above every one on disk, so an archive
+    /// name is never reused.
+    next: u64,
+    /// Serializes compressing and pruning, which run off the async workers.
+    archiving: Arc<Mutex<()>>,
+}
+impl RotatingFile {
+    pub fn open(path: &Path, max_bytes: u64, keep: usize) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        }
+        let file = open_append(path)?;
+        let written = file.metadata()?.len();
+        let leftovers = rotated(path, false)?;
+        let next = rotated(path, true)?
+            .iter()
+            .chain(&leftovers)
+            .map(|(n, _)| n + 1)
+            .max()
+            .unwrap_or(1);
+        let out = Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            keep,
+            file,
+            written,
+            next,
+            archiving: Arc::new(Mutex::new(())),
+        };
+        out.archive(leftovers.into_iter().map(|(_, p)| p).collect())?;
+        Ok(out)
+    }
+    pub fn write(&mut self, line: &[u8]) -> Result<()> {
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.flush()?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+    pub fn rotate(&mut self) -> Result<()> {
+        self.flush()?;
+        let rotated = sibling(&self.path, &format!(".{}", self.next));
+        fs::rename(&self.path, &rotated)
+            .with_context(|| format!("rotating {}", self.path.display()))?;
+        self.next += 1;
+        self.file = open_append(&self.path)?;
+        self.written = 0;
+        self.archive(vec![rotated])
+    }
+    /// Compresses rotated files and prunes old archives, on the blocking pool
+    /// when called from the runtime (failures are then logged) and inline
+    /// otherwise. Files left uncompressed by an exit are handled on the next open.
+    fn archive(&self, files: Vec<PathBuf>) -> Result<()> {
+        let (path, keep, lock) = (self.path.clone(), self.keep, self.archiving.clone());
+        let job = move || {
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+            for f in &files {
+                compress(&path, f)?;
+            }
+            prune(&path, keep)
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(rt) => {
+                rt.spawn_blocking(move || {
+                    if let Err(e) = job() {
+                        warn!(error=?e, "archiving rotated output failed");
+                    }
+                });
+                Ok(())
+            }
+            Err(_) => job(),
+        }
+    }
+}
+fn compress(path: &Path, rotated: &Path) -> Result<()> {
+    let archive = sibling(rotated, ".zst");
+    let mut input = BufReader::new(File::open(rotated)?);
+    let mut enc = zstd::stream::Encoder::new(File::create(&archive)?, 3)?;
+    io::copy(&mut input, &mut enc).with_context(|| format!("compressing {}", rotated.display()))?;
+    enc.finish()?.sync_all()?;
+    fs::remove_file(rotated)?;
+    sync_dir(path);
+    Ok(())
+}
+fn prune(path: &Path, keep: usize) -> Result<()> {
+    let mut archives = rotated(path, true)?;
+    archives.sort();
+    let excess = archives.len().saturating_sub(keep);
+    for (_, old) in archives.into_iter().take(excess) {
+        fs::remove_file(&old).with_context(|| format!("removing {}", old.display()))?;
+    }
+    Ok(())
+}
+/// Rotated files of `path` (`<name>.<n>`, or `<name>.<n>.zst` when `compressed`)
+/// with their numbers.
+fn rotated(path: &Path, compressed: bool) -> Result<Vec<(u64, PathBuf)>> {
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let prefix = format!(
+        "{}.",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let mut out = Vec::new();
+    for e in fs::read_dir(dir)? {
+        let p = e?.path();
+        let name = p
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let Some(rest) = name.strip_prefix(&prefix) else {
+            continue;
+        };
+        let n = match (rest.strip_suffix(".zst"), compressed) {
+            (Some(n), true) => n,
+            (None, false) => rest,
+            _ => continue,
+        };
+        if let Ok(n) = n.parse::<u64>() {
+            out.push((n, p));
+        }
+    }
+    Ok(out)
+}
+impl super::Sink for RotatingFile {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+    fn write(&mut self, _kind: &str, line: &[u8]) -> Result<()> {
+        RotatingFile::write(self, line)
+    }
+    fn flush(&mut self) -> Result<()> {
+        RotatingFile::flush(self)
+    }
+}
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("opening {}", path.display()))
+}
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut s = path.as_os_str().to_os_string();
+    s.push(suffix);
+    s.into()
+}
+#[cfg(unix)]
+fn sync_dir(path: &Path) {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        if let Ok(d) = File::open(dir) {
+            let _ = d.sync_all();
+        }
+    }
+}
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn archives(path: &Path) -> Vec<(u64, Vec<u8>)> {
+        let mut out: Vec<_> = rotated(path, true)
+            .unwrap()
+            .into_iter()
+            .map(|(n, p)| (n, zstd::stream::decode_all(File::open(p).unwrap()).unwrap()))
+            .collect();
+        out.sort();
+        out
+    }
+    #[test]
+    fn rotates_compresses_and_keeps_n_archives() {
+        let dir = std::env::temp_dir().join(format!("rea-file-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("telemetry.jsonl");
+        let mut f = RotatingFile::open(&path, 64, 10).unwrap();
+        for i in 0..5u8 {
+            f.write(&[b'0' + i; 40]).unwrap();
+        }
+        let want: Vec<_> = (0..4u8)
+            .map(|i| (i as u64 + 1, vec![b'0' + i; 40]))
+            .collect();
+        assert_eq!(archives(&path), want);
+        assert!(rotated(&path, false).unwrap().is_empty());
+        assert_eq!(fs::metadata(&path).unwrap().len(), 40);
+        drop(f);
+        let mut f = RotatingFile::open(&path, 64, 2).unwrap();
+        assert_eq!(archives(&path), want[2..]);
+        f.write(&[b'5'; 40]).unwrap();
+        let names: Vec<_> = archives(&path).into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, [4, 5]);
+        fs::remove_dir_all(&dir).ok();
+    }
+    #[tokio::test]
+    async fn compresses_off_the_runtime_without_losing_archives() {
+        let dir = std::env::temp_dir().join(format!("rea-file-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("telemetry.jsonl");
+        let mut f = RotatingFile::open(&path, 64, 10).unwrap();
+        for i in 0..5u8 {
+            f.write(&[b'0' + i; 40]).unwrap();
+        }
+        let lock = f.archiving.clone();
+        tokio::task::spawn_blocking(move || {
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+            while rotated(&path, true).unwrap().len() < 4 && std::time::Instant::now() < deadline {
+                drop(lock.lock().unwrap());
+                std::thread::yield_now();
+            }
+            assert_eq!(archives(&path).len(), 4);
+            assert!(rotated(&path, false).unwrap().is_empty());
+        })
+        .await
+        .unwrap();
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,244 @@
+use crate::config::{AgentConfig, SinkConfig, SinkType};
+use crate::shutdown::Shutdown;
+use anyhow::{bail, Context, Result};
+use std::io::Write;
+use std::sync::mpsc as std_mpsc;
+#[cfg(feature = "networking")]
+use tokio::sync::mpsc::{error::TrySendError, Sender};
+use tracing::warn;
+pub mod file;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod syslog;
+pub trait Sink: Send {
+    fn name(&self) -> &'static str;
+    fn write(&mut self, kind: &str, line: &[u8]) -> Result<()>;
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+struct Route {
+    kinds: Vec<String>,
+    sink: Box<dyn Sink>,
+}
+pub struct Outputs {
+    routes: Vec<Route>,
+}
+impl Outputs {
+    pub fn build(
+        cfg: &AgentConfig,
+        #[cfg(feature = "networking")] net_tx: Option<Sender<Vec<u8>>>,
+        shutdown: &Shutdown,
+    ) -> Result<Self> {
+        let mut routes = Vec::new();
+        for SinkConfig { sink, kinds } in cfg.output.effective_sinks(cfg.networking.enabled) {
+            let sink: Box<dyn Sink> = match sink {
+                SinkType::Stdout => Threaded::spawn(Box::new(StdoutSink), shutdown)?,
+                SinkType::File {
+                    path,
+                    rotate_bytes,
+                    keep_files,
+                } => Threaded::spawn(
+                    Box::new(file::RotatingFile::open(
+                        &path,
+                        rotate_bytes.unwrap_or(cfg.output.rotate_bytes) as u64,
+                        keep_files.unwrap_or(cfg.output.keep_files),
+                    )?),
+                    shutdown,
+                )?,
+                SinkType::Syslog {
+                    transport,
+                    address,
+                    facility,
+                    ca_cert,
+                } => Threaded::spawn(
+                    Box::new(syslog::SyslogSink::new(
+                        transport,
+                        address.as_deref(),
+                        facility,
+                        ca_cert.as_deref(),
+                        &cfg.common.instance_id,
+                    )?),
+                    shutdown,
+                )?,
+                #[cfg(feature = "otlp")]
+                SinkType::Otlp { endpoint, headers } => Box::new(otlp::OtlpSink::new(
+                    &endpoint,
+                    &headers,
+                    &cfg.common.instance_id,
+                    &cfg.networking.proxy,
+                    shutdown,
+                )?),
+                #[cfg(not(feature = "otlp"))]
+                SinkType::Otlp { .. } => {
+                    warn!("otlp sink configured but the `otlp` feature is off");
+                    continue;
+                }
+                #[cfg(feature = "networking")]
+                SinkType::Network => match &net_tx {
+                    Some(tx) => Box::new(NetworkSink(tx.clone())),
+                    None => continue,
+                },
+                #[cfg(not(feature = "networking"))]
+                SinkType::Network => {
+                    warn!("network sink configured but the `networking` feature is off");
+                    continue;
+                }
+            };
+            routes.push(Route { kinds, sink });
+        }
+        Ok(Self { routes })
+    }
+    pub fn dispatch(&mut self, kind: &str, line: &[u8]) {
+        for r in &mut self.routes {
+            if !r.kinds.is_empty() && !r.kinds.iter().any(|k| k == kind) {
+                continue;
+            }
+            if let Err(e) = r.sink.write(kind, line) {
+                warn!(sink = r.sink.name(), error=?e, "sink write failed");
+                crate::metrics::event_dropped(kind, r.sink.name());
+            }
+        }
+    }
+    pub fn flush(&mut self) {
+        for r in &mut self.routes {
+            if let Err(e) = r.sink.flush() {
+                warn!(sink = r.sink.name(), error=?e, "sink flush failed");
+            }
+        }
+    }
+}
+/// Queue of a sink running on its own thread.
+const WRITER_QUEUE: usize = 1024;
+enum Op {
+    Write(String, Vec<u8>),
+    Flush,
+}
+/// Runs a sink that does blocking I/O (stdout, files, syslog) on its own
+/// thread, so a slow disk or an unreachable syslog server never stalls the
+/// collectors. Once dropped, the thread finishes its queue and flushes; shutdown
+/// waits for that.
+struct Threaded {
+    name: &'static str,
+    tx: std_mpsc::SyncSender<Op>,
+}
+impl Threaded {
+    fn spawn(mut sink: Box<dyn Sink>, shutdown: &Shutdown) -> Result<Box<dyn Sink>> {
+        let name = sink.name();
+        let (tx, rx) = std_mpsc::sync_channel(WRITER_QUEUE);
+        let (done_tx, done_rx) = tokio::sync::oneshot::channel::<()>();
+        std::thread::Builder::new()
+            .name(format!("rea-{name}-sink"))
+            .spawn(move || {
+                let _done = done_tx;
+                for op in rx {
+                    match op {
+                        Op::Write(kind, line) => {
+                            if let Err(e) = sink.write(&kind, &line) {
+                                warn!(sink = name, error=?e, "sink write failed");
+                                crate::metrics::event_dropped(&kind, name);
+                            }
+                        }
+                        Op::Flush => {
+                            if let Err(e) = sink.flush() {
+                                warn!(sink = name, error=?e, "sink flush failed");
+                            }
+                        }
+                    }
+                }
+                if let Err(e) = sink.flush() {
+                    warn!(sink = name, error=?e, "sink flush failed");
+                }
+            })
+            .with_context(|| format!("starting the {name} sink thread"))?;
+        shutdown.spawn(async move {
+            let _ = done_rx.await;
+        });
+        Ok(Box::new(Self { name, tx }))
+    }
+}
+impl Sink for Threaded {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    fn write(&mut self, kind: &str, line: &[u8]) -> Result<()> {
+        match self.tx.try_send(Op::Write(kind.to_string(), line.to_vec())) {
+            Ok(()) => Ok(()),
+            Err(std_mpsc::TrySendError::Full(_)) => bail!("{} sink busy; event dropped", self.name),
+            Err(std_mpsc::TrySendError::Disconnected(_)) => bail!("{} sink stopped", self.name),
+        }
+    }
+    fn flush(&mut self) -> Result<()> {
+        let _ = self.tx.try_send(Op::Flush);
+        Ok(())
+    }
+}
+pub struct StdoutSink;
+impl Sink for StdoutSink {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+    fn write(&mut self, _kind: &str, line: &[u8]) -> Result<()> {
+        std::io::stdout().lock().write_all(line)?;
+        Ok(())
+    }
+    fn flush(&mut self) -> Result<()> {
+        std::io::stdout().lock().flush()?;
+        Ok(())
+    }
+}
+#[cfg(feature = "networking")]
+pub struct NetworkSink(Sender<Vec<u8>>);
+#[cfg(feature = "networking")]
+impl Sink for NetworkSink {
+    fn name(&self) -> &'static str {
+        "network"
+    }
+    fn write(&mut self, _kind: &str, line: &[u8]) -> Result<()> {
+        match self.0.try_send(line.to_vec()) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => bail!("sender channel full; event dropped"),
+            Err(TrySendError::Closed(_)) => bail!("network sender stopped"),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, time::Duration};
+    #[tokio::test]
+    async fn dispatch_sends_each_kind_only_to_its_sinks() {
+        let dir = std::env::temp_dir().join(format!("rea-outputs-{}", uuid::Uuid::new_v4()));
+        let file = |name: &str, kinds: &[&str]| SinkConfig {
+            sink: SinkType::File {
+                path: dir.join(name),
+                rotate_bytes: None,
+                keep_files: None,
+            },
+            kinds: kinds.iter().map(|k| k.to_string()).collect(),
+        };
+        let mut cfg = AgentConfig::default();
+        cfg.output.sinks = vec![
+            file("metrics.jsonl", &["cpu", "mem"]),
+            file("alerts.jsonl", &["alert"]),
+        ];
+        let shutdown = Shutdown::default();
+        let mut outputs = Outputs::build(
+            &cfg,
+            #[cfg(feature = "networking")]
+            None,
+            &shutdown,
+        )
+        .unwrap();
+        for kind in ["cpu", "alert", "disk", "mem"] {
+            outputs.dispatch(kind, format!("{kind}\n").as_bytes());
+        }
+        outputs.flush();
+        drop(outputs);
+        assert!(shutdown.drain(Duration::from_secs(5)).await);
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("metrics.jsonl"), "cpu\nmem\n");
+        assert_eq!(read("alerts.jsonl"), "alert\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
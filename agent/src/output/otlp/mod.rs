@@ -0,0 +1,519 @@
+use self::proto::{
+    any_value, metric, number_data_point, AnyValue, ExportLogsServiceRequest,
+    ExportMetricsServiceRequest, Gauge, InstrumentationScope, KeyValue, LogRecord, Metric,
+    NumberDataPoint, Resource, ResourceLogs, ResourceMetrics, ScopeLogs, ScopeMetrics, Sum,
+};
+use crate::{
+    config::Proxy,
+    shutdown::Shutdown,
+    transport::mtls::{build_client, TlsMaterials},
+};
+use anyhow::{bail, Context, Result};
+use prost::Message;
+use reqwest::header::HeaderMap;
+use serde_json::Value;
+use std::{collections::BTreeMap, sync::OnceLock, time::Duration};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+use tracing::warn;
+pub mod proto;
+const MAX_BATCH: usize = 256;
+const FLUSH_EVERY: Duration = Duration::from_secs(5);
+enum Msg {
+    Event(Value),
+    Flush,
+}
+/// Maps telemetry envelopes to OTLP metrics and logs and posts them as protobuf over HTTP.
+pub struct OtlpSink(Sender<Msg>);
+impl OtlpSink {
+    /// Starts the exporter task, which goes through `proxy` like the network
+    /// sender and exports what it holds when `shutdown` fires.
+    pub fn new(
+        endpoint: &str,
+        headers: &BTreeMap<String, String>,
+        instance_id: &str,
+        proxy: &Proxy,
+        shutdown: &Shutdown,
+    ) -> Result<Self> {
+        let mut hm = HeaderMap::new();
+        for (k, v) in headers {
+            hm.insert(
+                reqwest::header::HeaderName::from_bytes(k.as_bytes())
+                    .with_context(|| format!("invalid OTLP header name {k}"))?,
+                v.parse()
+                    .with_context(|| format!("invalid value for OTLP header {k}"))?,
+            );
+        }
+        let tls = TlsMaterials {
+            ca: None,
+            identity: None,
+            not_after: None,
+        };
+        let client = build_client(&tls, proxy)?;
+        series_start();
+        let (tx, rx) = mpsc::channel(4 * MAX_BATCH);
+        shutdown.spawn(run(
+            rx,
+            client,
+            hm,
+            endpoint.trim_end_matches('/').to_string(),
+            resource(instance_id),
+            shutdown.clone(),
+        ));
+        Ok(Self(tx))
+    }
+}
+impl super::Sink for OtlpSink {
+    fn name(&self) -> &'static str {
+        "otlp"
+    }
+    fn write(&mut self, _kind: &str, line: &[u8]) -> Result<()> {
+        let env: Value = serde_json::from_slice(line)?;
+        match self.0.try_send(Msg::Event(env)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => bail!("otlp exporter busy; event dropped"),
+            Err(TrySendError::Closed(_)) => bail!("otlp exporter stopped"),
+        }
+    }
+    fn flush(&mut self) -> Result<()> {
+        let _ = self.0.try_send(Msg::Flush);
+        Ok(())
+    }
+}
+/// Batches events until the sink is dropped. On shutdown it exports at once and
+/// keeps going until the collectors drop the sink.
+async fn run(
+    mut rx: Receiver<Msg>,
+    client: reqwest::Client,
+    headers: HeaderMap,
+    endpoint: String,
+    res: Resource,
+    stop: Shutdown,
+) {
+    let (mut metrics, mut logs) = (Vec::new(), Vec::new());
+    let mut tick = tokio::time::interval(FLUSH_EVERY);
+    let mut stopping = false;
+    loop {
+        let done = tokio::select! {
+            // Events already sent are taken before the shutdown export.
+            biased;
+            msg = rx.recv() => match msg {
+                Some(Msg::Event(env)) => {
+                    match env["kind"].as_str().unwrap_or_default() {
+                        "cpu" | "mem" | "disk" | "net" | "proc" | "os" => {
+                            metrics.extend(to_metrics(&env))
+                        }
+                        _ => logs.push(to_log(&env)),
+                    }
+                    if metrics.len() + logs.len() < MAX_BATCH {
+                        continue;
+                    }
+                    false
+                }
+                Some(Msg::Flush) => false,
+                None => true,
+            },
+            _ = tick.tick() => false,
+            _ = stop.cancelled(), if !stopping => {
+                stopping = true;
+                false
+            }
+        };
+        export(&client, &headers, &endpoint, &res, &mut metrics, &mut logs).await;
+        if done {
+            break;
+        }
+    }
+}
+async fn export(
+    client: &reqwest::Client,
+    headers: &HeaderMap,
+    endpoint: &str,
+    res: &Resource,
+    metrics: &mut Vec<Metric>,
+    logs: &mut Vec<LogRecord>,
+) {
+    if !metrics.is_empty() {
+        let req = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(res.clone()),
+                scope_metrics: vec![ScopeMetrics {
+                    scope: Some(scope()),
+                    metrics: std::mem::take(metrics),
+                }],
+            }],
+        };
+        if let Err(e) = post(
+            client,
+            headers,
+            &format!("{endpoint}/v1/metrics"),
+            req.encode_to_vec(),
+        )
+        .await
+        {
+            warn!(error=?e, "otlp metrics export failed");
+        }
+    }
+    if !logs.is_empty() {
+        let req = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(res.clone()),
+                scope_logs: vec![ScopeLogs {
+                    scope: Some(scope()),
+                    log_records: std::mem::take(logs),
+                }],
+            }],
+        };
+        let url = format!("{endpoint}/v1/logs");
+        if let Err(e) = post(client, headers, &url, req.encode_to_vec()).await {
+            warn!(error=?e, "otlp logs export failed");
+        }
+    }
+}
+async fn post(
+    client: &reqwest::Client,
+    headers: &HeaderMap,
+    url: &str,
+    body: Vec<u8>,
+) -> Result<()> {
+    let resp = client
+        .post(url)
+        .headers(headers.clone())
+        .timeout(Duration::from_secs(10))
+        .header("Content-Type", "application/x-protobuf")
+        .body(body)
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        bail!("{url} returned {}", resp.status());
+    }
+    Ok(())
+}
+fn scope() -> InstrumentationScope {
+    InstrumentationScope {
+        name: "rea-agent".into(),
+        version: env!("CARGO_PKG_VERSION").into(),
+    }
+}
+fn resource(instance_id: &str) -> Resource {
+    let mut attributes = vec![
+        kv("service.name", "rea-agent"),
+        kv("service.instance.id", instance_id),
+        kv("instance_id", instance_id),
+        kv("os.type", std::env::consts::OS),
+    ];
+    if let Some(h) = sysinfo::System::host_name() {
+        attributes.push(kv("host.name", &h));
+    }
+    if let Some(d) = sysinfo::System::long_os_version() {
+        attributes.push(kv("os.description", &d));
+    }
+    Resource { attributes }
+}
+fn kv(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.into(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.into())),
+        }),
+    }
+}
+/// Start of the cumulative series: when the first exporter was created.
+fn series_start() -> u64 {
+    static START: OnceLock<u64> = OnceLock::new();
+    *START.get_or_init(|| OffsetDateTime::now_utc().unix_timestamp_nanos() as u64)
+}
+fn ts_nanos(env: &Value) -> u64 {
+    env["ts"]
+        .as_str()
+        .and_then(|t| OffsetDateTime::parse(t, &Rfc3339).ok())
+        .unwrap_or_else(OffsetDateTime::now_utc)
+        .unix_timestamp_nanos() as u64
+}
+fn point(t: u64, v: &Value, attrs: &[(&str, &str)]) -> Option<NumberDataPoint> {
+    let value = match v {
+        Value::Number(n) if n.is_u64() || n.is_i64() => {
+            number_data_point::Value::AsInt(n.as_i64().unwrap_or(i64::MAX))
+        }
+        Value::Number(n) => number_data_point::Value::AsDouble(n.as_f64()?),
+        _ => return None,
+    };
+    Some(NumberDataPoint {
+        attributes: attrs.iter().map(|(k, v)| kv(k, v)).collect(),
+        start_time_unix_nano: 0,
+        time_unix_nano: t,
+        value: Some(value),
+    })
+}
+fn gauge(name: &str, unit: &str, points: Vec<NumberDataPoint>) -> Metric {
+    Metric {
+        name: name.into(),
+        description: String::new(),
+        unit: unit.into(),
+        data: Some(metric::Data::Gauge(Gauge {
+            data_points: points,
+        })),
+    }
+}
+fn counter(name: &str, unit: &str, mut points: Vec<NumberDataPoint>) -> Metric {
+    for p in &mut points {
+        p.start_time_unix_nano = series_start().min(p.time_unix_nano);
+    }
+    Metric {
+        name: name.into(),
+        description: String::new(),
+        unit: unit.into(),
+        data: Some(metric::Data::Sum(Sum {
+            data_points: points,
+            aggregation_temporality: proto::AGGREGATION_TEMPORALITY_CUMULATIVE,
+            is_monotonic: true,
+        })),
+    }
+}
+fn items(v: &Value) -> &[Value] {
+    v.as_array().map(Vec::as_slice).unwrap_or_default()
+}
+/// Converts a collector envelope (cpu, mem, disk, net, proc, os) into OTLP metrics using
+/// the OpenTelemetry system semantic conventions where one exists.
+pub fn to_metrics(env: &Value) -> Vec<Metric> {
+    let (t, b) = (ts_nanos(env), &env["body"]);
+    let p = |v: &Value, attrs: &[(&str, &str)]| point(t, v, attrs).into_iter().collect::<Vec<_>>();
+    let str_of = |v: &Value| v.as_str().unwrap_or_default().to_string();
+    let mut out = Vec::new();
+    match env["kind"].as_str().unwrap_or_default() {
+        "cpu" => {
+            let util = b["global_cpu_percent"]
+                .as_f64()
+                .map(|c| Value::from(c / 100.0));
+            out.push(gauge(
+                "system.cpu.utilization",
+                "1",
+                p(&util.unwrap_or_default(), &[]),
+            ));
+            for (field, name) in [
+                ("load_avg_one", "system.cpu.load_average.1m"),
+                ("load_avg_five", "system.cpu.load_average.5m"),
+                ("load_avg_fifteen", "system.cpu.load_average.15m"),
+            ] {
+                out.push(gauge(name, "{thread}", p(&b[field], &[])));
+            }
+        }
+        "mem" => {
+            let mut pts = p(&b["used"], &[("system.memory.state", "used")]);
+            pts.extend(p(&b["free"], &[("system.memory.state", "free")]));
+            out.push(gauge("system.memory.usage", "By", pts));
+            out.push(gauge("system.memory.limit", "By", p(&b["total"], &[])));
+        }
+        "disk" => {
+            let (mut usage, mut limit) = (Vec::new(), Vec::new());
+            for m in items(&b["mounts"]) {
+                let dev = str_of(&m["name"]);
+                let (total, avail) = (m["total"].as_u64(), m["available"].as_u64());
+                let used = Value::from(total.unwrap_or(0).saturating_sub(avail.unwrap_or(0)));
+                usage.extend(p(
+                    &used,
+                    &[("system.device", &dev), ("system.filesystem.state", "used")],
+                ));
+                usage.extend(p(
+                    &m["available"],
+                    &[("system.device", &dev), ("system.filesystem.state", "free")],
+                ));
+                limit.extend(p(&m["total"], &[("system.device", &dev)]));
+            }
+            out.push(gauge("system.filesystem.usage", "By", usage));
+            out.push(gauge("system.filesystem.limit", "By", limit));
+        }
+        "net" => {
+            let mut pts = Vec::new();
+            for i in items(&b["ifaces"]) {
+                let name = str_of(&i["name"]);
+                pts.extend(p(
+                    &i["total_received"],
+                    &[
+                        ("network.interface.name", &name),
+                        ("network.io.direction", "receive"),
+                    ],
+                ));
+                pts.extend(p(
+                    &i["total_transmitted"],
+                    &[
+                        ("network.interface.name", &name),
+                        ("network.io.direction", "transmit"),
+                    ],
+                ));
+            }
+            out.push(counter("system.network.io", "By", pts));
+        }
+        "proc" => {
+            out.push(gauge(
+                "system.process.count",
+                "{process}",
+                p(&b["process_count"], &[]),
+            ));
+            let (mut cpu, mut mem) = (Vec::new(), Vec::new());
+            for pr in items(&b["top"]) {
+                let (pid, name) = (pr["pid"].to_string(), str_of(&pr["name"]));
+                let attrs = [
+                    ("process.pid", pid.as_str()),
+                    ("process.executable.name", name.as_str()),
+                ];
+                let util = pr["cpu_percent"].as_f64().map(|c| Value::from(c / 100.0));
+                cpu.extend(p(&util.unwrap_or_default(), &attrs));
+                mem.extend(p(&pr["mem_bytes"], &attrs));
+            }
+            out.push(gauge("process.cpu.utilization", "1", cpu));
+            out.push(gauge("process.memory.usage", "By", mem));
+        }
+        "os" => out.push(gauge("system.uptime", "s", p(&b["uptime_secs"], &[]))),
+        _ => {}
+    }
+    out.retain(|m| match &m.data {
+        Some(metric::Data::Gauge(g)) => !g.data_points.is_empty(),
+        Some(metric::Data::Sum(s)) => !s.data_points.is_empty(),
+        None => false,
+    });
+    out
+}
+/// Converts an event envelope (alerts, journald, Windows events, FIM, ...) into an OTLP log
+/// record whose body is the event JSON.
+pub fn to_log(env: &Value) -> LogRecord {
+    let kind = env["kind"].as_str().unwrap_or_default();
+    let (severity_number, severity_text) = match kind {
+        "alert" => (proto::SEVERITY_WARN, "WARN"),
+        _ => (proto::SEVERITY_INFO, "INFO"),
+    };
+    LogRecord {
+        time_unix_nano: ts_nanos(env),
+        observed_time_unix_nano: OffsetDateTime::now_utc().unix_timestamp_nanos() as u64,
+        severity_number,
+        severity_text: severity_text.into(),
+        body: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(env["body"].to_string())),
+        }),
+        attributes: vec![
+            kv("event.name", kind),
+            kv("event.id", env["event_id"].as_str().unwrap_or_default()),
+        ],
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{output::Sink, test_support::read_request};
+    use serde_json::json;
+    use tokio::{io::AsyncWriteExt, net::TcpListener};
+    #[tokio::test]
+    async fn exports_metrics_and_logs_as_protobuf() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, mut rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            loop {
+                let (mut s, _) = listener.accept().await.unwrap();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let mut buf = Vec::new();
+                    while let Some(req) = read_request(&mut s, &mut buf).await {
+                        s.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                            .await
+                            .unwrap();
+                        let _ = tx.send(req).await;
+                    }
+                });
+            }
+        });
+        let headers = BTreeMap::from([("x-api-key".to_string(), "secret".to_string())]);
+        let direct = Proxy {
+            url: "direct".into(),
+            ..Default::default()
+        };
+        let shutdown = Shutdown::default();
+        let endpoint = format!("http://{addr}/");
+        let mut sink = OtlpSink::new(&endpoint, &headers, "agent-1", &direct, &shutdown).unwrap();
+        let cpu = json!({
+            "ts": "2025-01-01T00:00:00Z", "event_id": "e1", "instance_id": "agent-1", "kind": "cpu",
+            "body": {"global_cpu_percent": 50.0, "load_avg_one": 1.0,
+                     "load_avg_five": 0.5, "load_avg_fifteen": 0.25},
+        });
+        let alert = json!({
+            "ts": "2025-01-01T00:00:01Z", "event_id": "e2", "instance_id": "agent-1", "kind": "alert",
+            "body": {"rule_id": "cpu-high", "state": "firing"},
+        });
+        sink.write("cpu", cpu.to_string().as_bytes()).unwrap();
+        sink.write("alert", alert.to_string().as_bytes()).unwrap();
+        sink.flush().unwrap();
+        let mut got = BTreeMap::new();
+        for _ in 0..2 {
+            let (head, body) = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(head
+                .to_ascii_lowercase()
+                .contains("content-type: application/x-protobuf"));
+            assert!(head.contains("x-api-key: secret"));
+            got.insert(head.split_whitespace().nth(1).unwrap().to_string(), body);
+        }
+        let m = ExportMetricsServiceRequest::decode(got["/v1/metrics"].as_slice()).unwrap();
+        let rm = &m.resource_metrics[0];
+        let attrs: Vec<_> = rm
+            .resource
+            .as_ref()
+            .unwrap()
+            .attributes
+            .iter()
+            .map(|a| a.key.as_str())
+            .collect();
+        assert!(
+            attrs.contains(&"host.name")
+                && attrs.contains(&"instance_id")
+                && attrs.contains(&"os.type")
+        );
+        let util = &rm.scope_metrics[0].metrics[0];
+        assert_eq!(util.name, "system.cpu.utilization");
+        let Some(metric::Data::Gauge(g)) = &util.data else {
+            panic!("expected gauge")
+        };
+        assert_eq!(
+            g.data_points[0].value,
+            Some(number_data_point::Value::AsDouble(0.5))
+        );
+        assert_eq!(g.data_points[0].time_unix_nano, 1_735_689_600_000_000_000);
+        let l = ExportLogsServiceRequest::decode(got["/v1/logs"].as_slice()).unwrap();
+        let rec = &l.resource_logs[0].scope_logs[0].log_records[0];
+        assert_eq!(rec.severity_number, proto::SEVERITY_WARN);
+        assert!(rec.attributes.contains(&kv("event.name", "alert")));
+        sink.write("alert", alert.to_string().as_bytes()).unwrap();
+        shutdown.trigger();
+        let (head, _) = tokio::time::timeout(Duration::from_secs(2), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(head.starts_with("POST /v1/logs "));
+        drop(sink);
+        assert!(shutdown.drain(Duration::from_secs(5)).await);
+    }
+    #[test]
+    fn maps_network_counters_per_direction() {
+        let start = series_start();
+        let now = OffsetDateTime::now_utc().format(&Rfc3339).unwrap();
+        let env = json!({"kind": "net", "ts": now,
+            "body": {"ifaces": [{"name": "eth0", "total_received": 10, "total_transmitted": 20}]}});
+        let m = to_metrics(&env);
+        assert_eq!(m.len(), 1);
+        let Some(metric::Data::Sum(s)) = &m[0].data else {
+            panic!("expected sum")
+        };
+        assert!(s.is_monotonic);
+        assert_eq!(s.data_points.len(), 2);
+        assert_eq!(s.data_points[0].start_time_unix_nano, start);
+        assert!(start < s.data_points[0].time_unix_nano);
+        assert_eq!(
+            s.data_points[1].value,
+            Some(number_data_point::Value::AsInt(20))
+        );
+        assert!(s.data_points[1]
+            .attributes
+            .contains(&kv("network.io.direction", "transmit")));
+    }
+}
@@ -0,0 +1,344 @@
+use super::Sink;
+use crate::config::SyslogTransport;
+use anyhow::{bail, Context, Result};
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::io::{BufReader, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+const SD_ID: &str = "rea@32473";
+const TIMEOUT: Duration = Duration::from_secs(5);
+enum Conn {
+    Closed,
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+pub struct SyslogSink {
+    transport: SyslogTransport,
+    address: String,
+    facility: u8,
+    host: String,
+    instance_id: String,
+    tls: Option<Arc<ClientConfig>>,
+    conn: Conn,
+}
+impl SyslogSink {
+    pub fn new(
+        transport: SyslogTransport,
+        address: Option<&str>,
+        facility: u8,
+        ca_cert: Option<&Path>,
+        instance_id: &str,
+    ) -> Result<Self> {
+        let address = address
+            .map(str::to_string)
+            .unwrap_or_else(|| default_address(transport).into());
+        let tls = match transport {
+            SyslogTransport::Tls => Some(tls_config(
+                ca_cert.context("syslog over TLS needs ca_cert")?,
+            )?),
+            _ => None,
+        };
+        Ok(Self {
+            transport,
+            address,
+            facility,
+            host: sysinfo::System::host_name().unwrap_or_default(),
+            instance_id: instance_id.to_string(),
+            tls,
+            conn: Conn::Closed,
+        })
+    }
+    fn connect(&self) -> Result<Conn> {
+        Ok(match self.transport {
+            SyslogTransport::Udp => {
+                let addr = resolve(&self.address)?;
+                let any = if addr.is_ipv6() {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                };
+                let sock = UdpSocket::bind(any)?;
+                sock.connect(addr)
+                    .with_context(|| format!("syslog udp {}", self.address))?;
+                Conn::Udp(sock)
+            }
+            SyslogTransport::Tcp => Conn::Tcp(tcp_connect(&self.address)?),
+            SyslogTransport::Tls => {
+                let tcp = tcp_connect(&self.address)?;
+                let host = self
+                    .address
+                    .rsplit_once(':')
+                    .map_or(&*self.address, |(h, _)| h);
+                let name = ServerName::try_from(host.trim_matches(['[', ']']).to_string())
+                    .with_context(|| format!("bad TLS server name `{host}`"))?;
+                let tls = self.tls.clone().context("TLS config missing")?;
+                let conn = ClientConnection::new(tls, name)?;
+                Conn::Tls(Box::new(StreamOwned::new(conn, tcp)))
+            }
+            #[cfg(unix)]
+            SyslogTransport::Unix => {
+                let sock = UnixDatagram::unbound()?;
+                sock.connect(&self.address)
+                    .with_context(|| format!("syslog socket {}", self.address))?;
+                Conn::Unix(sock)
+            }
+            #[cfg(not(unix))]
+            SyslogTransport::Unix => {
+                bail!("unix syslog sockets are not available on this platform")
+            }
+        })
+    }
+    fn send(&mut self, msg: &[u8]) -> Result<()> {
+        if matches!(self.conn, Conn::Closed) {
+            self.conn = self.connect()?;
+        }
+        let framed = |msg: &[u8]| {
+            let mut f = format!("{} ", msg.len()).into_bytes();
+            f.extend_from_slice(msg);
+            f
+        };
+        match &mut self.conn {
+            Conn::Closed => bail!("syslog connection closed"),
+            Conn::Udp(s) => {
+                s.send(msg)?;
+            }
+            Conn::Tcp(s) => s.write_all(&framed(msg))?,
+            Conn::Tls(s) => {
+                s.write_all(&framed(msg))?;
+                s.flush()?;
+            }
+            #[cfg(unix)]
+            Conn::Unix(s) => {
+                s.send(msg)?;
+            }
+        }
+        Ok(())
+    }
+}
+impl Sink for SyslogSink {
+    fn name(&self) -> &'static str {
+        "syslog"
+    }
+    fn write(&mut self, kind: &str, line: &[u8]) -> Result<()> {
+        let severity = if kind == "alert" { 4 } else { 6 };
+        let msg = format_5424(
+            self.facility,
+            severity,
+            &self.host,
+            kind,
+            &[("instance_id", &self.instance_id), ("kind", kind)],
+            line.strip_suffix(b"\n").unwrap_or(line),
+        );
+        if self.send(&msg).is_err() {
+            self.conn = Conn::Closed;
+            self.send(&msg)?;
+        }
+        Ok(())
+    }
+}
+fn default_address(t: SyslogTransport) -> &'static str {
+    match t {
+        SyslogTransport::Udp | SyslogTransport::Tcp => "127.0.0.1:514",
+        SyslogTransport::Tls => "127.0.0.1:6514",
+        SyslogTransport::Unix => "/dev/log",
+    }
+}
+fn resolve(address: &str) -> Result<SocketAddr> {
+    address
+        .to_socket_addrs()
+        .with_context(|| format!("resolving {address}"))?
+        .next()
+        .with_context(|| format!("no address for {address}"))
+}
+fn tcp_connect(address: &str) -> Result<TcpStream> {
+    let addr = resolve(address)?;
+    let s = TcpStream::connect_timeout(&addr, TIMEOUT)
+        .with_context(|| format!("syslog tcp {address}"))?;
+    s.set_write_timeout(Some(TIMEOUT))?;
+    Ok(s)
+}
+fn tls_config(ca: &Path) -> Result<Arc<ClientConfig>> {
+    let mut roots = RootCertStore::empty();
+    let mut rd = BufReader::new(
+        std::fs::File::open(ca).with_context(|| format!("reading CA {}", ca.display()))?,
+    );
+    for cert in rustls_pemfile::certs(&mut rd) {
+        roots.add(cert.context("parsing CA PEM")?)?;
+    }
+    let cfg =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    Ok(Arc::new(cfg))
+}
+fn header_field(s: &str, max: usize) -> String {
+    let v: String = s
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max)
+        .collect();
+    if v.is_empty() {
+        "-".into()
+    } else {
+        v
+    }
+}
+fn sd_escape(v: &str) -> String {
+    let mut out = String::with_capacity(v.len());
+    for c in v.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+pub fn format_5424(
+    facility: u8,
+    severity: u8,
+    host: &str,
+    msgid: &str,
+    params: &[(&str, &str)],
+    msg: &[u8],
+) -> Vec<u8> {
+    // TIME-SECFRAC has at most six digits.
+    let now = OffsetDateTime::now_utc();
+    let ts = (now - time::Duration::nanoseconds(i64::from(now.nanosecond() % 1_000)))
+        .format(&Rfc3339)
+        .unwrap_or_else(|_| "-".into());
+    let sd: String = params
+        .iter()
+        .map(|(k, v)| format!(" {k}=\"{}\"", sd_escape(v)))
+        .collect();
+    let mut out = format!(
+        "<{}>1 {ts} {} rea-agent {} {} [{SD_ID}{sd}] ",
+        u16::from(facility) * 8 + u16::from(severity),
+        header_field(host, 255),
+        std::process::id(),
+        header_field(msgid, 32),
+    )
+    .into_bytes();
+    out.extend_from_slice(msg);
+    out
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    const LINE: &[u8] = b"{\"kind\":\"cpu\",\"body\":{}}\n";
+    fn check(msg: &str) {
+        assert!(msg.starts_with("<134>1 "), "{msg}");
+        let ts = msg.split(' ').nth(1).unwrap();
+        assert!(OffsetDateTime::parse(ts, &Rfc3339).is_ok(), "{msg}");
+        if let Some((_, frac)) = ts.split_once('.') {
+            assert!(frac.trim_end_matches('Z').len() <= 6, "{msg}");
+        }
+        assert!(
+            msg.contains(" rea-agent ")
+                && msg.contains(" cpu [rea@32473 instance_id=\"i-\\\"1\\\"\" kind=\"cpu\"] "),
+            "{msg}"
+        );
+        assert!(msg.ends_with("{\"kind\":\"cpu\",\"body\":{}}"), "{msg}");
+    }
+    #[test]
+    fn udp_delivers_one_datagram_per_event() {
+        let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        rx.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let addr = rx.local_addr().unwrap().to_string();
+        let mut sink =
+            SyslogSink::new(SyslogTransport::Udp, Some(&addr), 16, None, "i-\"1\"").unwrap();
+        sink.write("cpu", LINE).unwrap();
+        let mut buf = [0u8; 2048];
+        let n = rx.recv(&mut buf).unwrap();
+        check(std::str::from_utf8(&buf[..n]).unwrap());
+    }
+    fn check_frames(all: &str, n: usize) {
+        let mut rest = all;
+        for _ in 0..n {
+            let (len, tail) = rest.split_once(' ').unwrap();
+            let len: usize = len.parse().unwrap();
+            check(&tail[..len]);
+            rest = &tail[len..];
+        }
+        assert!(rest.is_empty());
+    }
+    #[test]
+    fn tcp_uses_octet_counting() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let reader = std::thread::spawn(move || {
+            let (mut s, _) = listener.accept().unwrap();
+            let mut all = Vec::new();
+            s.read_to_end(&mut all).unwrap();
+            String::from_utf8(all).unwrap()
+        });
+        let mut sink =
+            SyslogSink::new(SyslogTransport::Tcp, Some(&addr), 16, None, "i-\"1\"").unwrap();
+        sink.write("cpu", LINE).unwrap();
+        sink.write("cpu", LINE).unwrap();
+        drop(sink);
+        check_frames(&reader.join().unwrap(), 2);
+    }
+    #[test]
+    fn tls_verifies_the_server_and_uses_octet_counting() {
+        let ck = rcgen::generate_simple_self_signed(vec!["127.0.0.1".into()]).unwrap();
+        let ca = std::env::temp_dir().join(format!("rea-syslog-{}.crt", uuid::Uuid::new_v4()));
+        std::fs::write(&ca, ck.cert.pem()).unwrap();
+        let mut server = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![ck.cert.der().clone()],
+            rustls::pki_types::PrivateKeyDer::Pkcs8(ck.key_pair.serialize_der().into()),
+        )
+        .unwrap();
+        // Unread tickets would make the client's close a reset.
+        server.send_tls13_tickets = 0;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let reader = std::thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let conn = rustls::ServerConnection::new(Arc::new(server)).unwrap();
+            let mut s = StreamOwned::new(conn, tcp);
+            let mut all = Vec::new();
+            // The sink closes without close_notify; what arrived is kept.
+            let _ = s.read_to_end(&mut all);
+            String::from_utf8(all).unwrap()
+        });
+        let mut sink =
+            SyslogSink::new(SyslogTransport::Tls, Some(&addr), 16, Some(&ca), "i-\"1\"").unwrap();
+        sink.write("cpu", LINE).unwrap();
+        sink.write("cpu", LINE).unwrap();
+        drop(sink);
+        check_frames(&reader.join().unwrap(), 2);
+        std::fs::remove_file(&ca).ok();
+    }
+    #[cfg(unix)]
+    #[test]
+    fn unix_datagram_socket() {
+        let path = std::env::temp_dir().join(format!("rea-syslog-{}.sock", uuid::Uuid::new_v4()));
+        let rx = UnixDatagram::bind(&path).unwrap();
+        rx.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let mut sink =
+            SyslogSink::new(SyslogTransport::Unix, path.to_str(), 16, None, "i-\"1\"").unwrap();
+        sink.write("cpu", LINE).unwrap();
+        let mut buf = [0u8; 2048];
+        let n = rx.recv(&mut buf).unwrap();
+        check(std::str::from_utf8(&buf[..n]).unwrap());
+        std::fs::remove_file(&path).ok();
+    }
+}
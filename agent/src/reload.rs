@@ -0,0 +1,191 @@
+use crate::config::{diff, layers::drop_in_dir, load_layered, validate, AgentConfig, CliOverrides};
+use anyhow::{Context, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tracing::{info, warn};
+pub fn spawn_config_reloader(
+    path: Option<PathBuf>,
+    optional: bool,
+    overrides: CliOverrides,
+    initial: AgentConfig,
+) -> Result<watch::Receiver<AgentConfig>> {
+    let (tx, rx) = watch::channel(initial);
+    let (kick_tx, mut kick_rx) = mpsc::channel::<()>(1);
+    let watcher = match &path {
+        Some(p) => watch_file(p, kick_tx.clone())?,
+        None => None,
+    };
+    #[cfg(feature = "networking")]
+    crate::transport::remote::spawn_poller(
+        rx.clone(),
+        path.clone(),
+        optional,
+        overrides.clone(),
+        kick_tx.clone(),
+    );
+    #[cfg(unix)]
+    forward_sighup(kick_tx)?;
+    tokio::spawn(async move {
+        let _watcher = watcher;
+        while kick_rx.recv().await.is_some() {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            while kick_rx.try_recv().is_ok() {}
+            let current = tx.borrow().clone();
+            if let Some(next) = reload(&current, path.as_deref(), optional, &overrides) {
+                tx.send_replace(next);
+            }
+        }
+    });
+    Ok(rx)
+}
+/// The config to switch to, or `None` when it is unchanged or invalid; an
+/// invalid one is logged with the changes it would have made.
+fn reload(
+    current: &AgentConfig,
+    path: Option<&Path>,
+    optional: bool,
+    overrides: &CliOverrides,
+) -> Option<AgentConfig> {
+    let next = match load_layered(path, optional, overrides) {
+        Ok(l) => {
+            for w in &l.warnings {
+                warn!("{w}");
+            }
+            l.config
+        }
+        Err(e) => {
+            warn!(error=?e, "invalid config; keeping current");
+            return None;
+        }
+    };
+    if next == *current {
+        return None;
+    }
+    let changes = diff(current, &next);
+    if let Err(e) = validate(&next) {
+        warn!(error=?e, rejected=?changes, "invalid config; keeping current");
+        return None;
+    }
+    info!(?changes, "config reloaded");
+    Some(next)
+}
+/// Watches the config file and its drop-ins; `None` when its directory does not
+/// exist (an optional config), in which case only SIGHUP triggers a reload.
+fn watch_file(path: &Path, kick: mpsc::Sender<()>) -> Result<Option<RecommendedWatcher>> {
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() {
+        info!(
+            dir = %dir.display(),
+            "config directory does not exist; not watching it for changes"
+        );
+        return Ok(None);
+    }
+    let name = path.file_name().map(|n| n.to_os_string());
+    let drop_ins = drop_in_dir(path);
+    let in_drop_ins = drop_ins.clone();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(ev) = res {
+            if ev.kind.is_access() {
+                return;
+            }
+            if ev.paths.iter().any(|p| {
+                p.file_name() == name.as_deref()
+                    || (p.parent() == Some(in_drop_ins.as_path())
+                        && p.extension().is_some_and(|x| x == "toml"))
+            }) {
+                let _ = kick.try_send(());
+            }
+        }
+    })
+    .context("creating config file watcher")?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("watching {}", dir.display()))?;
+    if drop_ins.is_dir() {
+        watcher
+            .watch(&drop_ins, RecursiveMode::NonRecursive)
+            .with_context(|| format!("watching {}", drop_ins.display()))?;
+    }
+    Ok(Some(watcher))
+}
+#[cfg(unix)]
+fn forward_sighup(kick: mpsc::Sender<()>) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hup = signal(SignalKind::hangup()).context("installing SIGHUP handler")?;
+    tokio::spawn(async move {
+        while hup.recv().await.is_some() {
+            info!("SIGHUP received; reloading config");
+            let _ = kick.try_send(());
+        }
+    });
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    #[tokio::test]
+    async fn applies_valid_changes_and_keeps_the_current_config_on_invalid_ones() {
+        let dir = std::env::temp_dir().join(format!("rea-reload-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("agent.toml");
+        let write = |interval: &str| {
+            let body =
+                format!("[common]\ninstance_id = \"rea-test\"\ninterval_secs = {interval}\n");
+            fs::write(&path, body).unwrap();
+        };
+        write("5");
+        let initial = load_layered(Some(&path), false, &CliOverrides::default())
+            .unwrap()
+            .config;
+        let mut rx =
+            spawn_config_reloader(Some(path.clone()), false, CliOverrides::default(), initial)
+                .unwrap();
+        write("7");
+        let wait = Duration::from_secs(10);
+        tokio::time::timeout(wait, rx.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(rx.borrow_and_update().common.interval_secs, 7);
+        write("0");
+        let quiet = Duration::from_secs(1);
+        assert!(tokio::time::timeout(quiet, rx.changed()).await.is_err());
+        fs::write(&path, "[common\n").unwrap();
+        assert!(tokio::time::timeout(quiet, rx.changed()).await.is_err());
+        assert_eq!(rx.borrow().common.interval_secs, 7);
+        write("9");
+        tokio::time::timeout(wait, rx.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(rx.borrow_and_update().common.interval_secs, 9);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn a_missing_config_file_is_an_error() {
+        let path = std::env::temp_dir().join(format!("rea-missing-{}.toml", uuid::Uuid::new_v4()));
+        let err = load_layered(Some(&path), false, &CliOverrides::default())
+            .map(|_| ())
+            .unwrap_err();
+        assert!(err.to_string().contains("does not exist"));
+        assert!(err.to_string().contains("--config-optional"));
+    }
+    #[tokio::test]
+    async fn an_optional_config_in_a_missing_directory_starts_from_defaults() {
+        let dir = std::env::temp_dir().join(format!("rea-reload-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("etc").join("agent.toml");
+        let initial = load_layered(Some(&path), true, &CliOverrides::default())
+            .unwrap()
+            .config;
+        assert_eq!(initial, AgentConfig::default());
+        let rx = spawn_config_reloader(Some(path), true, CliOverrides::default(), initial);
+        assert!(rx.is_ok());
+        assert!(!dir.exists());
+    }
+}
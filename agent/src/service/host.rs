@@ -0,0 +1,127 @@
+use crate::shutdown::Shutdown;
+use anyhow::Result;
+use std::future::Future;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    StartPending,
+    Running,
+    StopPending,
+    Stopped { exit_code: u32 },
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Control {
+    Stop,
+    Shutdown,
+    Interrogate,
+    Unsupported,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlReply {
+    Handled,
+    NotImplemented,
+}
+/// What the agent needs from a service controller (SCM on Windows, a fake in tests).
+pub trait ServiceHost: Send + Sync {
+    fn report(&self, state: State) -> Result<()>;
+}
+/// Service lifecycle: reports start-up, maps stop/shutdown requests onto the
+/// agent's graceful shutdown and reports the final exit code.
+pub struct Lifecycle<H> {
+    host: H,
+    shutdown: Shutdown,
+}
+impl<H: ServiceHost> Lifecycle<H> {
+    pub fn new(host: H, shutdown: Shutdown) -> Self {
+        Self { host, shutdown }
+    }
+    pub fn host(&self) -> &H {
+        &self.host
+    }
+    pub fn on_control(&self, control: Control) -> ControlReply {
+        match control {
+            Control::Stop | Control::Shutdown => {
+                if let Err(e) = self.host.report(State::StopPending) {
+                    tracing::warn!(error=?e, "reporting stop-pending failed");
+                }
+                self.shutdown.trigger();
+                ControlReply::Handled
+            }
+            Control::Interrogate => ControlReply::Handled,
+            Control::Unsupported => ControlReply::NotImplemented,
+        }
+    }
+    pub async fn run<F>(&self, agent: F) -> Result<()>
+    where
+        F: Future<Output = Result<()>>,
+    {
+        self.host.report(State::StartPending)?;
+        self.host.report(State::Running)?;
+        let res = agent.await;
+        if let Err(e) = &res {
+            tracing::error!(error=?e, "agent failed");
+        }
+        self.host.report(State::Stopped {
+            exit_code: u32::from(res.is_err()),
+        })?;
+        res
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    #[derive(Clone, Default)]
+    struct FakeHost(Arc<Mutex<Vec<State>>>);
+    impl ServiceHost for FakeHost {
+        fn report(&self, state: State) -> Result<()> {
+            self.0.lock().unwrap().push(state);
+            Ok(())
+        }
+    }
+    #[tokio::test]
+    async fn stop_request_runs_graceful_shutdown_and_reports_transitions() {
+        let host = FakeHost::default();
+        let shutdown = Shutdown::default();
+        let lc = Arc::new(Lifecycle::new(host.clone(), shutdown.clone()));
+        let agent = {
+            let shutdown = shutdown.clone();
+            async move {
+                shutdown.cancelled().await;
+                Ok(())
+            }
+        };
+        let run = tokio::spawn({
+            let lc = lc.clone();
+            async move { lc.run(agent).await }
+        });
+        while host.0.lock().unwrap().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(lc.on_control(Control::Interrogate), ControlReply::Handled);
+        assert_eq!(
+            lc.on_control(Control::Unsupported),
+            ControlReply::NotImplemented
+        );
+        assert_eq!(lc.on_control(Control::Stop), ControlReply::Handled);
+        run.await.unwrap().unwrap();
+        assert_eq!(
+            *host.0.lock().unwrap(),
+            vec![
+                State::StartPending,
+                State::Running,
+                State::StopPending,
+                State::Stopped { exit_code: 0 },
+            ]
+        );
+    }
+    #[tokio::test]
+    async fn agent_failure_is_reported_as_non_zero_exit() {
+        let host = FakeHost::default();
+        let lc = Lifecycle::new(host.clone(), Shutdown::default());
+        assert!(lc.run(async { anyhow::bail!("boom") }).await.is_err());
+        assert_eq!(
+            host.0.lock().unwrap().last(),
+            Some(&State::Stopped { exit_code: 1 })
+        );
+    }
+}
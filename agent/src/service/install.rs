@@ -1,39 +1,72 @@
-#[cfg(target_os = "windows")]
+use super::spec::{ServiceSpec, StartMode};
 use anyhow::{Context, Result};
-#[cfg(target_os = "windows")]
-use std::path::Path;
-#[cfg(target_os = "windows")]
+use std::ffi::OsString;
 use windows_service::{
-    service::{ServiceAccess, ServiceErrorControl, ServiceInfo, ServiceStartType, ServiceType},
+    service::{
+        ServiceAccess, ServiceAction, ServiceActionType, ServiceDependency, ServiceErrorControl,
+        ServiceFailureActions, ServiceFailureResetPeriod, ServiceInfo, ServiceStartType,
+        ServiceType,
+    },
     service_manager::{ServiceManager, ServiceManagerAccess},
 };
-#[cfg(target_os = "windows")]
-pub fn install_service(display_name: &str, description: &str, config_path: &Path) -> Result<()> {
+fn service_info(spec: &ServiceSpec) -> Result<ServiceInfo> {
+    Ok(ServiceInfo {
+        name: spec.name.clone().into(),
+        display_name: spec.display_name.clone().into(),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: match spec.start_mode {
+            StartMode::Auto | StartMode::DelayedAuto => ServiceStartType::AutoStart,
+            StartMode::Manual => ServiceStartType::OnDemand,
+        },
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe().context("current_exe")?,
+        launch_arguments: spec.launch_arguments.clone(),
+        dependencies: spec
+            .dependencies
+            .iter()
+            .map(|d| ServiceDependency::Service(d.into()))
+            .collect(),
+        account_name: spec.account_name.as_ref().map(OsString::from),
+        account_password: spec.account_password.as_ref().map(OsString::from),
+    })
+}
+pub fn install_service(spec: &ServiceSpec) -> Result<()> {
     let mgr = ServiceManager::local_computer(
         None::<&str>,
         ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
     )?;
-    let exe = std::env::current_exe().context("current_exe")?;
-    let info = ServiceInfo {
-        name: "RustEndpointAgent".into(),
-        display_name: display_name.into(),
-        service_type: ServiceType::OWN_PROCESS,
-        start_type: ServiceStartType::Automatic,
-        error_control: ServiceErrorControl::Normal,
-        executable_path: exe,
-        launch_arguments: vec![
-            "run".into(),
-            "--config".into(),
-            config_path.display().to_string(),
-        ],
-        dependencies: vec![],
-        account_name: None,
-        account_password: None,
-    };
     let svc = mgr
-        .create_service(&info, ServiceAccess::all())
+        .create_service(&service_info(spec)?, ServiceAccess::all())
         .context("create service")?;
-    let _ = svc.set_description(description);
-    let _ = svc.start(&[]);
+    svc.set_description(&spec.description)
+        .context("set service description")?;
+    if spec.start_mode == StartMode::DelayedAuto {
+        svc.set_delayed_auto_start(true)
+            .context("enable delayed auto start")?;
+    }
+    if let Some(r) = &spec.recovery {
+        svc.update_failure_actions(ServiceFailureActions {
+            reset_period: ServiceFailureResetPeriod::After(r.reset_period),
+            reboot_msg: None,
+            command: None,
+            actions: Some(
+                r.restart_delays
+                    .iter()
+                    .map(|&delay| ServiceAction {
+                        action_type: ServiceActionType::Restart,
+                        delay,
+                    })
+                    .collect(),
+            ),
+        })
+        .context("set failure recovery actions")?;
+        // A non-zero exit code reported with SERVICE_STOPPED counts as a failure too.
+        svc.set_failure_actions_on_non_crash_failures(true)
+            .context("enable recovery on non-crash failures")?;
+    }
+    if spec.start {
+        svc.start::<&str>(&[])
+            .context("service installed but failed to start")?;
+    }
     Ok(())
 }
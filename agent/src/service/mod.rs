@@ -1,5 +1,13 @@
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+pub mod host;
 #[cfg(target_os = "windows")]
 pub mod install;
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+pub mod spec;
+#[cfg(target_os = "linux")]
+pub mod systemd;
 #[cfg(target_os = "windows")]
 pub mod uninstall;
-// SCM runs: agent.exe run --config <path>
+#[cfg(target_os = "windows")]
+pub mod windows;
+// SCM runs: agent.exe --config <path> service run
@@ -0,0 +1,214 @@
+use anyhow::{bail, Result};
+use std::{ffi::OsString, path::Path, str::FromStr, time::Duration};
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum StartMode {
+    Auto,
+    DelayedAuto,
+    Manual,
+}
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Account {
+    LocalSystem,
+    LocalService,
+    NetworkService,
+    /// `NT SERVICE\<service name>`, a per-service virtual account.
+    Virtual,
+    User(String),
+}
+impl FromStr for Account {
+    type Err = std::convert::Infallible;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "localsystem" => Self::LocalSystem,
+            "localservice" => Self::LocalService,
+            "networkservice" => Self::NetworkService,
+            "virtual" => Self::Virtual,
+            _ => Self::User(s.to_string()),
+        })
+    }
+}
+/// Windows service install options, parsed from `agent service install`.
+#[derive(Clone, Debug, clap::Args)]
+pub struct InstallOptions {
+    /// auto, delayed-auto or manual.
+    #[arg(long, value_enum, default_value_t = StartMode::Auto)]
+    pub start_mode: StartMode,
+    /// localsystem, localservice, networkservice, virtual or DOMAIN\user.
+    #[arg(long, default_value = "localsystem")]
+    pub account: Account,
+    /// Password for a DOMAIN\user account.
+    #[arg(long, env = "REA_SERVICE_PASSWORD", hide_env_values = true)]
+    pub password: Option<String>,
+    /// Services that must be running first (repeatable).
+    #[arg(long = "depends-on")]
+    pub dependencies: Vec<String>,
+    /// Seconds to wait before restarting after a failure.
+    #[arg(long, default_value_t = 10)]
+    pub restart_delay_secs: u64,
+    /// Restarts attempted before giving up until the reset period ends (1-3).
+    #[arg(long, default_value_t = 3)]
+    pub restart_attempts: u32,
+    /// Seconds without failure after which the failure count resets.
+    #[arg(long, default_value_t = 86400)]
+    pub reset_period_secs: u64,
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub no_recovery: bool,
+    /// Register only; do not start the service.
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub no_start: bool,
+}
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recovery {
+    pub restart_delays: Vec<Duration>,
+    pub reset_period: Duration,
+}
+/// Platform-neutral description of the service registration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceSpec {
+    pub name: String,
+    pub display_name: String,
+    pub description: String,
+    pub launch_arguments: Vec<OsString>,
+    pub start_mode: StartMode,
+    pub account_name: Option<String>,
+    pub account_password: Option<String>,
+    pub dependencies: Vec<String>,
+    pub recovery: Option<Recovery>,
+    pub start: bool,
+}
+impl ServiceSpec {
+    pub fn build(
+        name: &str,
+        display_name: &str,
+        description: &str,
+        config: &Path,
+        o: &InstallOptions,
+    ) -> Result<Self> {
+        let account_name = match &o.account {
+            Account::LocalSystem => None,
+            Account::LocalService => Some("NT AUTHORITY\\LocalService".to_string()),
+            Account::NetworkService => Some("NT AUTHORITY\\NetworkService".to_string()),
+            Account::Virtual => Some(format!("NT SERVICE\\{name}")),
+            Account::User(user) if user.trim().is_empty() => bail!("--account must not be empty"),
+            Account::User(user) => Some(user.clone()),
+        };
+        if o.password.is_some() && !matches!(o.account, Account::User(_)) {
+            bail!("--password is only used with a DOMAIN\\user account");
+        }
+        if let Some(d) = o.dependencies.iter().find(|d| d.trim().is_empty()) {
+            bail!("invalid --depends-on value {d:?}");
+        }
+        let recovery = if o.no_recovery {
+            None
+        } else {
+            if !(1..=3).contains(&o.restart_attempts) {
+                bail!("--restart-attempts must be between 1 and 3");
+            }
+            if o.restart_delay_secs == 0 {
+                bail!("--restart-delay-secs must be greater than zero");
+            }
+            Some(Recovery {
+                restart_delays: vec![
+                    Duration::from_secs(o.restart_delay_secs);
+                    o.restart_attempts as usize
+                ],
+                reset_period: Duration::from_secs(o.reset_period_secs),
+            })
+        };
+        Ok(Self {
+            name: name.into(),
+            display_name: display_name.into(),
+            description: description.into(),
+            launch_arguments: vec![
+                "--config".into(),
+                config.into(),
+                "service".into(),
+                "run".into(),
+            ],
+            start_mode: o.start_mode,
+            account_name,
+            account_password: o.password.clone(),
+            dependencies: o.dependencies.clone(),
+            recovery,
+            start: !o.no_start,
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        opts: InstallOptions,
+    }
+    fn spec(args: &[&str]) -> Result<ServiceSpec> {
+        let cli = Cli::try_parse_from(std::iter::once("install").chain(args.iter().copied()))?;
+        ServiceSpec::build(
+            "RustEndpointAgent",
+            "Rust Endpoint Agent",
+            "desc",
+            Path::new("C:\\ProgramData\\REA\\agent.toml"),
+            &cli.opts,
+        )
+    }
+    #[test]
+    fn defaults_to_auto_start_local_system_with_restart_recovery() {
+        let s = spec(&[]).unwrap();
+        assert_eq!(s.start_mode, StartMode::Auto);
+        assert_eq!(s.account_name, None);
+        assert!(s.start);
+        let r = s.recovery.unwrap();
+        assert_eq!(r.restart_delays, vec![Duration::from_secs(10); 3]);
+        assert_eq!(r.reset_period, Duration::from_secs(86400));
+        assert_eq!(
+            s.launch_arguments,
+            [
+                "--config",
+                "C:\\ProgramData\\REA\\agent.toml",
+                "service",
+                "run"
+            ]
+            .map(OsString::from)
+        );
+    }
+    #[test]
+    fn maps_accounts_dependencies_and_delayed_start() {
+        let s = spec(&[
+            "--start-mode",
+            "delayed-auto",
+            "--account",
+            "virtual",
+            "--depends-on",
+            "Tcpip",
+            "--depends-on",
+            "Dnscache",
+            "--restart-attempts",
+            "1",
+            "--no-start",
+        ])
+        .unwrap();
+        assert_eq!(s.start_mode, StartMode::DelayedAuto);
+        assert_eq!(
+            s.account_name.as_deref(),
+            Some("NT SERVICE\\RustEndpointAgent")
+        );
+        assert_eq!(s.dependencies, ["Tcpip", "Dnscache"]);
+        assert_eq!(s.recovery.unwrap().restart_delays.len(), 1);
+        assert!(!s.start);
+        let s = spec(&["--account", "CORP\\svc-rea", "--password", "pw"]).unwrap();
+        assert_eq!(s.account_name.as_deref(), Some("CORP\\svc-rea"));
+        assert_eq!(s.account_password.as_deref(), Some("pw"));
+    }
+    #[test]
+    fn rejects_inconsistent_options() {
+        assert!(spec(&["--account", "localservice", "--password", "pw"]).is_err());
+        assert!(spec(&["--restart-attempts", "4"]).is_err());
+        assert!(spec(&["--restart-delay-secs", "0"]).is_err());
+        assert!(spec(&["--restart-attempts", "9", "--no-recovery"])
+            .unwrap()
+            .recovery
+            .is_none());
+    }
+}
@@ -0,0 +1,198 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+pub const UNIT_NAME: &str = "rea-agent.service";
+pub struct UnitOptions<'a> {
+    pub description: &'a str,
+    pub exe: &'a Path,
+    pub config: &'a Path,
+    pub user: &'a str,
+}
+/// systemd integration rooted at `root` (normally `/`); with `dry_run` only files
+/// are written and the `systemctl` commands are printed instead of executed.
+pub struct Systemd {
+    root: PathBuf,
+    dry_run: bool,
+}
+fn quote(p: &Path) -> String {
+    let s = p.display().to_string();
+    if s.contains(char::is_whitespace) {
+        format!("\"{s}\"")
+    } else {
+        s
+    }
+}
+pub fn render_unit(o: &UnitOptions) -> String {
+    format!(
+        "[Unit]
+Description={desc}
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+Type=simple
+ExecStart={exe} --config {config} run
+ExecReload=/bin/kill -HUP $MAINPID
+User={user}
+Group={user}
+Restart=on-failure
+RestartSec=5s
+TimeoutStopSec=20s
+StateDirectory=rea-agent
+LogsDirectory=rea-agent
+Environment=XDG_DATA_HOME=%S/rea-agent
+UMask=0077
+NoNewPrivileges=yes
+ProtectSystem=strict
+ProtectHome=yes
+PrivateTmp=yes
+PrivateDevices=yes
+ProtectKernelTunables=yes
+ProtectKernelModules=yes
+ProtectKernelLogs=yes
+ProtectControlGroups=yes
+ProtectClock=yes
+ProtectHostname=yes
+RestrictSUIDSGID=yes
+RestrictRealtime=yes
+RestrictNamespaces=yes
+LockPersonality=yes
+MemoryDenyWriteExecute=yes
+SystemCallArchitectures=native
+CapabilityBoundingSet=
+AmbientCapabilities=
+
+[Install]
+WantedBy=multi-user.target
+",
+        desc = o.description,
+        exe = quote(o.exe),
+        config = quote(o.config),
+        user = o.user,
+    )
+}
+impl Systemd {
+    pub fn new(root: &Path, dry_run: bool) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            dry_run,
+        }
+    }
+    pub fn unit_path(&self) -> PathBuf {
+        self.root.join("etc/systemd/system").join(UNIT_NAME)
+    }
+    fn sysusers_path(&self) -> PathBuf {
+        self.root.join("etc/sysusers.d/rea-agent.conf")
+    }
+    fn run(&self, cmd: &str, args: &[&str]) -> Result<()> {
+        if self.dry_run {
+            println!("would run: {cmd} {}", args.join(" "));
+            return Ok(());
+        }
+        let status = Command::new(cmd)
+            .args(args)
+            .status()
+            .with_context(|| format!("running {cmd}"))?;
+        if !status.success() {
+            bail!("{cmd} {} failed with {status}", args.join(" "));
+        }
+        Ok(())
+    }
+    /// Writes the unit and a sysusers.d entry for the service user, then reloads
+    /// systemd and enables and starts the unit.
+    pub fn install(&self, o: &UnitOptions) -> Result<PathBuf> {
+        let unit = self.unit_path();
+        let users = self.sysusers_path();
+        for p in [&unit, &users] {
+            if let Some(dir) = p.parent() {
+                fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+            }
+        }
+        fs::write(
+            &users,
+            format!(
+                "u {} - \"Rust Endpoint Agent\" /var/lib/rea-agent\n",
+                o.user
+            ),
+        )
+        .with_context(|| format!("write {}", users.display()))?;
+        fs::write(&unit, render_unit(o)).with_context(|| format!("write {}", unit.display()))?;
+        self.run("systemd-sysusers", &[&users.display().to_string()])?;
+        self.run("systemctl", &["daemon-reload"])?;
+        self.run("systemctl", &["enable", "--now", UNIT_NAME])?;
+        Ok(unit)
+    }
+    pub fn uninstall(&self) -> Result<()> {
+        let unit = self.unit_path();
+        if !unit.exists() {
+            bail!("{} is not installed", unit.display());
+        }
+        self.run("systemctl", &["disable", "--now", UNIT_NAME])?;
+        fs::remove_file(&unit).with_context(|| format!("remove {}", unit.display()))?;
+        let _ = fs::remove_file(self.sysusers_path());
+        self.run("systemctl", &["daemon-reload"])
+    }
+    /// One line per fact: unit file location plus `systemctl` enablement and activity.
+    pub fn status(&self) -> Result<String> {
+        let unit = self.unit_path();
+        if !unit.exists() {
+            return Ok(format!("{UNIT_NAME}: not installed"));
+        }
+        let mut out = format!("{UNIT_NAME}: installed at {}", unit.display());
+        if !self.dry_run {
+            for query in ["is-enabled", "is-active"] {
+                let o = Command::new("systemctl")
+                    .args([query, UNIT_NAME])
+                    .output()
+                    .context("running systemctl")?;
+                out.push_str(&format!(
+                    "\n{query}: {}",
+                    String::from_utf8_lossy(&o.stdout).trim()
+                ));
+            }
+        }
+        Ok(out)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn dry_run_install_and_uninstall_under_root() {
+        let root = std::env::temp_dir().join(format!("rea-systemd-{}", uuid::Uuid::new_v4()));
+        let sd = Systemd::new(&root, true);
+        assert!(sd.status().unwrap().ends_with("not installed"));
+        let unit = sd
+            .install(&UnitOptions {
+                description: "Rust Endpoint Agent",
+                exe: Path::new("/usr/local/bin/agent"),
+                config: Path::new("/etc/rea/agent toml/agent.toml"),
+                user: "rea-agent",
+            })
+            .unwrap();
+        assert_eq!(unit, root.join("etc/systemd/system/rea-agent.service"));
+        let text = fs::read_to_string(&unit).unwrap();
+        for line in [
+            "ExecStart=/usr/local/bin/agent --config \"/etc/rea/agent toml/agent.toml\" run",
+            "User=rea-agent",
+            "NoNewPrivileges=yes",
+            "ProtectSystem=strict",
+            "Restart=on-failure",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line}");
+        }
+        assert!(
+            fs::read_to_string(root.join("etc/sysusers.d/rea-agent.conf"))
+                .unwrap()
+                .starts_with("u rea-agent ")
+        );
+        assert!(sd.status().unwrap().contains("installed at"));
+        sd.uninstall().unwrap();
+        assert!(!unit.exists());
+        assert!(sd.uninstall().is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -9,7 +9,7 @@ use windows_service::{
 pub fn uninstall_service() -> Result<()> {
     let mgr = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
     let svc = mgr
-        .open_service("RustEndpointAgent", ServiceAccess::all())
+        .open_service(super::windows::SERVICE_NAME, ServiceAccess::all())
         .context("open service")?;
     if let Ok(st) = svc.query_status() {
         if st.current_state != ServiceState::Stopped {
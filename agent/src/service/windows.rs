@@ -0,0 +1,96 @@
+use super::host::{Control, ControlReply, Lifecycle, ServiceHost, State};
+use crate::shutdown::Shutdown;
+use anyhow::{Context, Result};
+use std::{
+    ffi::OsString,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+        ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+    service_dispatcher,
+};
+pub const SERVICE_NAME: &str = "RustEndpointAgent";
+type AgentFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+/// What `service_main` runs; the SCM calls it on its own thread without our arguments.
+pub struct Entry {
+    pub runtime: tokio::runtime::Handle,
+    pub agent: Box<dyn FnOnce(Shutdown) -> AgentFuture + Send>,
+}
+static ENTRY: Mutex<Option<Entry>> = Mutex::new(None);
+define_windows_service!(ffi_service_main, service_main);
+/// Hands the process to the SCM dispatcher; returns once the service has stopped.
+pub fn start_dispatcher(entry: Entry) -> Result<()> {
+    *ENTRY.lock().unwrap() = Some(entry);
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+        .context("starting service dispatcher (is the process running under the SCM?)")
+}
+fn service_main(_args: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        tracing::error!(error=?e, "service failed");
+    }
+}
+struct ScmHost(Mutex<Option<ServiceStatusHandle>>);
+impl ServiceHost for ScmHost {
+    fn report(&self, state: State) -> Result<()> {
+        let Some(handle) = *self.0.lock().unwrap() else {
+            return Ok(());
+        };
+        let (current_state, exit_code, wait_hint) = match state {
+            State::StartPending => (ServiceState::StartPending, 0, Duration::from_secs(10)),
+            State::Running => (ServiceState::Running, 0, Duration::ZERO),
+            State::StopPending => (ServiceState::StopPending, 0, Duration::from_secs(20)),
+            State::Stopped { exit_code } => (ServiceState::Stopped, exit_code, Duration::ZERO),
+        };
+        let controls_accepted = if current_state == ServiceState::Running {
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+        } else {
+            ServiceControlAccept::empty()
+        };
+        handle.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state,
+            controls_accepted,
+            exit_code: ServiceExitCode::Win32(exit_code),
+            checkpoint: 0,
+            wait_hint,
+            process_id: None,
+        })?;
+        Ok(())
+    }
+}
+fn run_service() -> Result<()> {
+    let entry = ENTRY
+        .lock()
+        .unwrap()
+        .take()
+        .context("service entry not set")?;
+    let shutdown = Shutdown::default();
+    let lc = Arc::new(Lifecycle::new(ScmHost(Mutex::new(None)), shutdown.clone()));
+    let handler = {
+        let lc = lc.clone();
+        move |ctrl| {
+            let control = match ctrl {
+                ServiceControl::Stop => Control::Stop,
+                ServiceControl::Shutdown | ServiceControl::Preshutdown => Control::Shutdown,
+                ServiceControl::Interrogate => Control::Interrogate,
+                _ => Control::Unsupported,
+            };
+            match lc.on_control(control) {
+                ControlReply::Handled => ServiceControlHandlerResult::NoError,
+                ControlReply::NotImplemented => ServiceControlHandlerResult::NotImplemented,
+            }
+        }
+    };
+    let handle = service_control_handler::register(SERVICE_NAME, handler)
+        .context("registering service control handler")?;
+    *lc.host().0.lock().unwrap() = Some(handle);
+    entry.runtime.block_on(lc.run((entry.agent)(shutdown)))
+}
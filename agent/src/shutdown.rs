@@ -0,0 +1,58 @@
+use std::{future::Future, time::Duration};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{info, warn};
+/// Cancellation signal shared by collectors, the network sender and the status
+/// server, plus the tasks that must finish (e.g. the final queue flush) before exit.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    tasks: TaskTracker,
+}
+impl Shutdown {
+    pub fn trigger(&self) {
+        self.token.cancel();
+    }
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(task);
+    }
+    /// Waits up to `grace` for tracked tasks; returns false if some were still running.
+    pub async fn drain(&self, grace: Duration) -> bool {
+        self.tasks.close();
+        tokio::time::timeout(grace, self.tasks.wait()).await.is_ok()
+    }
+    /// Triggers shutdown on Ctrl-C or SIGTERM.
+    pub fn on_signals(&self) {
+        let s = self.clone();
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            info!("shutdown requested");
+            s.trigger();
+        });
+    }
+}
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+            }
+            Err(e) => {
+                warn!(error=?e, "SIGTERM handler unavailable; Ctrl-C only");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,27 @@
+//! Helpers shared by the tests of the network sender and the exporters.
+use tokio::io::{AsyncRead, AsyncReadExt};
+/// Reads the next HTTP/1.1 request off `s`: its head, without the blank line,
+/// and its `content-length` body. `buf` keeps bytes read past the request for
+/// the next call on a kept-alive connection. `None` once the peer closes.
+pub async fn read_request<S: AsyncRead + Unpin>(
+    s: &mut S,
+    buf: &mut Vec<u8>,
+) -> Option<(String, Vec<u8>)> {
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&buf[..end]).into_owned();
+            let len: usize = head
+                .lines()
+                .filter_map(|l| l.split_once(':'))
+                .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+                .map_or(0, |(_, v)| v.trim().parse().unwrap());
+            if buf.len() >= end + 4 + len {
+                let body = buf.drain(..end + 4 + len).skip(end + 4).collect();
+                return Some((head, body));
+            }
+        }
+        let n = s.read(&mut chunk).await.ok().filter(|n| *n > 0)?;
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
@@ -1,32 +1,61 @@
-use super::mtls::{build_client, load_tls};
-use crate::config::AgentConfig;
+use super::mtls::{build_client, TlsPaths};
+use crate::config::{AgentConfig, Compression};
 use anyhow::{Context, Result};
 use reqwest::Client;
+use time::OffsetDateTime;
 pub struct NetClient {
     client: Client,
+    paths: TlsPaths,
+    snapshot: Vec<Option<Vec<u8>>>,
+    not_after: Option<OffsetDateTime>,
 }
 impl NetClient {
+    #[cfg(feature = "networking")]
     pub async fn new(cfg: &AgentConfig) -> Result<Self> {
-        let tls = load_tls(
-            cfg.networking.ca_cert.as_deref(),
-            cfg.networking.client_cert.as_deref(),
-            cfg.networking.client_key.as_deref(),
-        )?;
-        let client = build_client(&tls)?;
-        Ok(Self { client })
+        let paths = TlsPaths::for_config(cfg);
+        let snapshot = paths.snapshot();
+        let tls = paths.load()?;
+        Ok(Self {
+            client: build_client(&tls, &cfg.networking.proxy)?,
+            paths,
+            snapshot,
+            not_after: tls.not_after,
+        })
+    }
+    /// Expiry of the client certificate in use, if there is one.
+    pub fn not_after(&self) -> Option<OffsetDateTime> {
+        self.not_after
+    }
+    /// Swaps in a new client when the TLS files in effect changed since the last
+    /// call; `Ok(true)` when it did. A change that fails to load keeps the current
+    /// client and is not retried until the files change again, so a cert written
+    /// before its key is picked up once the key follows.
+    #[cfg(feature = "networking")]
+    pub fn refresh_tls(&mut self, cfg: &AgentConfig) -> Result<bool> {
+        let paths = TlsPaths::for_config(cfg);
+        let snapshot = paths.snapshot();
+        if paths == self.paths && snapshot == self.snapshot {
+            return Ok(false);
+        }
+        self.paths = paths;
+        self.snapshot = snapshot;
+        let tls = self.paths.load()?;
+        self.client = build_client(&tls, &cfg.networking.proxy)?;
+        self.not_after = tls.not_after;
+        Ok(true)
     }
     pub async fn post_ndjson(
         &self,
         endpoint: &str,
         lines: Vec<Vec<u8>>,
-        compression: &str,
+        compression: Compression,
     ) -> Result<()> {
         let mut body = Vec::new();
         for mut l in lines {
             body.append(&mut l);
         }
         let mut req = self.client.post(endpoint);
-        if compression.eq_ignore_ascii_case("zstd") {
+        if compression == Compression::Zstd {
             let compressed = zstd::stream::encode_all(&body[..], 3).context("zstd compress")?;
             req = req
                 .header("Content-Type", "application/x-ndjson")
@@ -44,3 +73,307 @@ impl NetClient {
         Ok(())
     }
 }
+/// Days left when the certificate expires within `warn_days` (0 disables).
+pub fn expiring_in_days(
+    not_after: Option<OffsetDateTime>,
+    warn_days: u64,
+    now: OffsetDateTime,
+) -> Option<i64> {
+    let left = (not_after? - now).whole_days();
+    (warn_days > 0 && left < warn_days as i64).then_some(left)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_support::read_request, transport::mtls::build_client_with_env};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use rustls::pki_types::PrivateKeyDer;
+    use std::{collections::HashMap, fs, sync::Arc};
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::Mutex,
+    };
+    fn write_identity(dir: &std::path::Path) {
+        let ck = rcgen::generate_simple_self_signed(vec!["rea-test".into()]).unwrap();
+        fs::write(dir.join("client.crt"), ck.cert.pem()).unwrap();
+        fs::write(dir.join("client.key"), ck.key_pair.serialize_pem()).unwrap();
+    }
+    #[tokio::test]
+    async fn rebuilds_only_when_tls_files_change_and_keeps_client_on_bad_files() {
+        let dir = std::env::temp_dir().join(format!("rea-tls-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        write_identity(&dir);
+        let mut cfg = AgentConfig::default();
+        cfg.networking.client_cert = Some(dir.join("client.crt"));
+        cfg.networking.client_key = Some(dir.join("client.key"));
+        let mut client = NetClient::new(&cfg).await.unwrap();
+        let first = client.not_after().unwrap();
+        assert!(!client.refresh_tls(&cfg).unwrap());
+        write_identity(&dir);
+        assert!(client.refresh_tls(&cfg).unwrap());
+        assert!(client.not_after().unwrap() >= first);
+        assert!(!client.refresh_tls(&cfg).unwrap());
+        fs::write(dir.join("client.key"), "not a key").unwrap();
+        assert!(client.refresh_tls(&cfg).is_err());
+        assert!(!client.refresh_tls(&cfg).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn warns_inside_the_expiry_window_only() {
+        let now = OffsetDateTime::now_utc();
+        let in_days = |d| Some(now + time::Duration::days(d) + time::Duration::hours(1));
+        assert_eq!(expiring_in_days(in_days(3), 14, now), Some(3));
+        assert_eq!(expiring_in_days(in_days(20), 14, now), None);
+        assert_eq!(expiring_in_days(in_days(3), 0, now), None);
+        assert_eq!(expiring_in_days(None, 14, now), None);
+        let expired = Some(now - time::Duration::days(2));
+        assert_eq!(expiring_in_days(expired, 14, now), Some(-2));
+    }
+    /// Reads one request (head and `content-length` body) and answers 200.
+    async fn respond_ok<S: AsyncRead + AsyncWrite + Unpin>(mut s: S) {
+        if read_request(&mut s, &mut Vec::new()).await.is_some() {
+            let _ = s
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await;
+        }
+    }
+    /// HTTPS ingest stand-in with a self-signed certificate for 127.0.0.1 that
+    /// counts its connections.
+    async fn tls_ingest(dir: &std::path::Path) -> (String, Arc<Mutex<usize>>) {
+        let ck = rcgen::generate_simple_self_signed(vec!["127.0.0.1".into()]).unwrap();
+        fs::write(dir.join("ca.crt"), ck.cert.pem()).unwrap();
+        let key = PrivateKeyDer::Pkcs8(ck.key_pair.serialize_der().into());
+        let tls = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![ck.cert.der().clone()], key)
+        .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(tls));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(Mutex::new(0));
+        let hits = connections.clone();
+        tokio::spawn(async move {
+            loop {
+                let (tcp, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                let hits = hits.clone();
+                tokio::spawn(async move {
+                    *hits.lock().await += 1;
+                    if let Ok(s) = acceptor.accept(tcp).await {
+                        respond_ok(s).await;
+                    }
+                });
+            }
+        });
+        (format!("https://{addr}/ingest"), connections)
+    }
+    /// CONNECT proxy stand-in: records each request head, then tunnels.
+    async fn connect_proxy() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let heads = seen.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut client, _) = listener.accept().await.unwrap();
+                let heads = heads.clone();
+                tokio::spawn(async move {
+                    let mut head = Vec::new();
+                    let mut byte = [0u8; 1];
+                    while !head.ends_with(b"\r\n\r\n") {
+                        if client.read(&mut byte).await.unwrap_or(0) == 0 {
+                            return;
+                        }
+                        head.push(byte[0]);
+                    }
+                    let head = String::from_utf8_lossy(&head).into_owned();
+                    let target = head
+                        .split_whitespace()
+                        .nth(1)
+                        .unwrap_or_default()
+                        .to_string();
+                    heads.lock().await.push(head);
+                    let Ok(mut upstream) = TcpStream::connect(&target).await else {
+                        return;
+                    };
+                    client
+                        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                        .await
+                        .unwrap();
+                    let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+                });
+            }
+        });
+        (url, seen)
+    }
+    async fn read_field(s: &mut TcpStream) -> String {
+        let mut len = [0u8; 1];
+        s.read_exact(&mut len).await.unwrap();
+        let mut v = vec![0u8; usize::from(len[0])];
+        s.read_exact(&mut v).await.unwrap();
+        String::from_utf8(v).unwrap()
+    }
+    /// SOCKS5 proxy stand-in requiring username/password auth: records the
+    /// credentials and target of each connection, then tunnels.
+    async fn socks5_proxy() -> (String, Arc<Mutex<Vec<(String, String)>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("socks5://{}", listener.local_addr().unwrap());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut client, _) = listener.accept().await.unwrap();
+                let log = log.clone();
+                tokio::spawn(async move {
+                    let mut greeting = [0u8; 2];
+                    client.read_exact(&mut greeting).await.unwrap();
+                    let mut methods = vec![0u8; usize::from(greeting[1])];
+                    client.read_exact(&mut methods).await.unwrap();
+                    assert!(methods.contains(&2), "no username/password offer");
+                    client.write_all(&[5, 2]).await.unwrap();
+                    let mut version = [0u8; 1];
+                    client.read_exact(&mut version).await.unwrap();
+                    let user = read_field(&mut client).await;
+                    let pass = read_field(&mut client).await;
+                    client.write_all(&[1, 0]).await.unwrap();
+                    let mut req = [0u8; 4];
+                    client.read_exact(&mut req).await.unwrap();
+                    let host = match req[3] {
+                        1 => {
+                            let mut ip = [0u8; 4];
+                            client.read_exact(&mut ip).await.unwrap();
+                            std::net::Ipv4Addr::from(ip).to_string()
+                        }
+                        3 => read_field(&mut client).await,
+                        _ => return,
+                    };
+                    let mut port = [0u8; 2];
+                    client.read_exact(&mut port).await.unwrap();
+                    let target = format!("{host}:{}", u16::from_be_bytes(port));
+                    log.lock()
+                        .await
+                        .push((format!("{user}:{pass}"), target.clone()));
+                    let Ok(mut upstream) = TcpStream::connect(&target).await else {
+                        return;
+                    };
+                    client
+                        .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                        .await
+                        .unwrap();
+                    let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+                });
+            }
+        });
+        (url, seen)
+    }
+    #[tokio::test]
+    async fn posts_through_connect_proxy_with_basic_auth_unless_host_is_exempt() {
+        let dir = std::env::temp_dir().join(format!("rea-proxy-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let (endpoint, connections) = tls_ingest(&dir).await;
+        let (proxy_url, seen) = connect_proxy().await;
+        fs::write(dir.join("proxy-pass"), "s3cret\n").unwrap();
+        let mut cfg = AgentConfig::default();
+        cfg.networking.ca_cert = Some(dir.join("ca.crt"));
+        cfg.networking.proxy.url = proxy_url;
+        cfg.networking.proxy.username = "rea".into();
+        cfg.networking.proxy.password = Some(format!("file:{}", dir.join("proxy-pass").display()));
+        let post = |cfg: AgentConfig| {
+            let endpoint = endpoint.clone();
+            async move {
+                NetClient::new(&cfg)
+                    .await?
+                    .post_ndjson(&endpoint, vec![b"{}\n".to_vec()], Compression::None)
+                    .await
+            }
+        };
+        post(cfg.clone()).await.unwrap();
+        let heads = seen.lock().await.clone();
+        assert_eq!(heads.len(), 1);
+        let host = endpoint
+            .trim_start_matches("https://")
+            .trim_end_matches("/ingest");
+        assert!(
+            heads[0].starts_with(&format!("CONNECT {host} ")),
+            "{}",
+            heads[0]
+        );
+        let auth = format!("Basic {}", STANDARD.encode("rea:s3cret"));
+        assert!(heads[0].contains(&auth), "{}", heads[0]);
+        assert_eq!(*connections.lock().await, 1);
+        cfg.networking.proxy.no_proxy = vec!["127.0.0.1".into()];
+        post(cfg.clone()).await.unwrap();
+        assert_eq!(seen.lock().await.len(), 1);
+        assert_eq!(*connections.lock().await, 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[tokio::test]
+    async fn posts_through_socks5_proxy_with_credentials() {
+        let dir = std::env::temp_dir().join(format!("rea-socks-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let (endpoint, connections) = tls_ingest(&dir).await;
+        let (proxy_url, seen) = socks5_proxy().await;
+        fs::write(dir.join("proxy-pass"), "s3cret\n").unwrap();
+        let mut cfg = AgentConfig::default();
+        cfg.networking.ca_cert = Some(dir.join("ca.crt"));
+        cfg.networking.proxy.url = proxy_url;
+        cfg.networking.proxy.username = "rea".into();
+        cfg.networking.proxy.password = Some(format!("file:{}", dir.join("proxy-pass").display()));
+        NetClient::new(&cfg)
+            .await
+            .unwrap()
+            .post_ndjson(&endpoint, vec![b"{}\n".to_vec()], Compression::None)
+            .await
+            .unwrap();
+        let host = endpoint
+            .trim_start_matches("https://")
+            .trim_end_matches("/ingest");
+        assert_eq!(
+            *seen.lock().await,
+            [("rea:s3cret".to_string(), host.to_string())]
+        );
+        assert_eq!(*connections.lock().await, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[tokio::test]
+    async fn falls_back_to_proxy_variables_unless_no_proxy_matches() {
+        let dir = std::env::temp_dir().join(format!("rea-proxy-env-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let (endpoint, connections) = tls_ingest(&dir).await;
+        let (proxy_url, seen) = connect_proxy().await;
+        let mut cfg = AgentConfig::default();
+        cfg.networking.ca_cert = Some(dir.join("ca.crt"));
+        let tls = TlsPaths::for_config(&cfg).load().unwrap();
+        let post = |vars: HashMap<&'static str, String>| {
+            let client =
+                build_client_with_env(&tls, &cfg.networking.proxy, &|k| vars.get(k).cloned())
+                    .unwrap();
+            let endpoint = endpoint.clone();
+            async move {
+                client
+                    .post(endpoint)
+                    .body("{}\n")
+                    .send()
+                    .await
+                    .unwrap()
+                    .error_for_status()
+                    .unwrap();
+            }
+        };
+        post(HashMap::from([("https_proxy", proxy_url.clone())])).await;
+        assert_eq!(seen.lock().await.len(), 1);
+        post(HashMap::from([
+            ("HTTPS_PROXY", proxy_url.clone()),
+            ("NO_PROXY", "127.0.0.1".into()),
+        ]))
+        .await;
+        assert_eq!(seen.lock().await.len(), 1);
+        assert_eq!(*connections.lock().await, 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+use crate::config::{Delivery, Endpoint, Networking};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use tracing::warn;
+/// A receiver and its circuit breaker: after `threshold` consecutive failures it
+/// is skipped until `open_until`; the next attempt after that is a trial that
+/// closes the breaker on success and reopens it on failure.
+struct Target {
+    endpoint: Endpoint,
+    failures: u32,
+    open_until: Option<Instant>,
+    last_error: Option<String>,
+    /// When the breaker first opened in the current outage.
+    down_since: Option<Instant>,
+    left_behind: bool,
+}
+/// Delivery state of every configured endpoint.
+pub struct Endpoints {
+    delivery: Delivery,
+    targets: Vec<Target>,
+    threshold: u32,
+    open_for: Duration,
+    max_lag: Option<Duration>,
+    turn: usize,
+}
+impl Endpoints {
+    pub fn new(net: &Networking) -> Self {
+        let endpoints = if net.endpoints.is_empty() {
+            vec![Endpoint {
+                url: net.endpoint.clone(),
+                ..Endpoint::default()
+            }]
+        } else {
+            net.endpoints.clone()
+        };
+        Self {
+            delivery: net.delivery,
+            targets: endpoints
+                .into_iter()
+                .map(|endpoint| Target {
+                    endpoint,
+                    failures: 0,
+                    open_until: None,
+                    last_error: None,
+                    down_since: None,
+                    left_behind: false,
+                })
+                .collect(),
+            threshold: net.breaker_failures.max(1),
+            open_for: Duration::from_secs(net.breaker_open_secs),
+            max_lag: Some(Duration::from_secs(net.fan_out_max_lag_secs)).filter(|d| !d.is_zero()),
+            turn: 0,
+        }
+    }
+    /// Never empty: without `endpoints` the single `endpoint` is used.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.targets.len()
+    }
+    pub fn url(&self, i: usize) -> &str {
+        &self.targets[i].endpoint.url
+    }
+    pub fn key(&self, i: usize) -> &str {
+        self.targets[i].endpoint.key()
+    }
+    pub fn available(&self, i: usize, now: Instant) -> bool {
+        self.targets[i].open_until.map_or(true, |t| now >= t)
+    }
+    /// Endpoints to try for the next batch, best first, without those whose
+    /// breaker is open. `round_robin` rotates the best priority tier per call.
+    pub fn order(&mut self, now: Instant) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.targets.len())
+            .filter(|&i| self.available(i, now))
+            .collect();
+        order.sort_by_key(|&i| self.targets[i].endpoint.priority);
+        if self.delivery == Delivery::RoundRobin && !order.is_empty() {
+            let best = self.targets[order[0]].endpoint.priority;
+            let tier = order
+                .iter()
+                .take_while(|&&i| self.targets[i].endpoint.priority == best)
+                .count();
+            order[..tier].rotate_left(self.turn % tier);
+            self.turn = self.turn.wrapping_add(1);
+        }
+        order
+    }
+    pub fn succeeded(&mut self, i: usize) {
+        let t = &mut self.targets[i];
+        t.failures = 0;
+        t.open_until = None;
+        t.last_error = None;
+        t.down_since = None;
+        t.left_behind = false;
+    }
+    pub fn failed(&mut self, i: usize, error: String, now: Instant) {
+        let t = &mut self.targets[i];
+        t.failures += 1;
+        t.last_error = Some(error);
+        if t.failures >= self.threshold {
+            t.open_until = Some(now + self.open_for);
+            t.down_since.get_or_insert(now);
+        }
+    }
+    /// Whether fan-out stops holding events for endpoint `i`: its breaker opened
+    /// `fan_out_max_lag_secs` or longer ago and it has not succeeded since. It
+    /// then misses the events trimmed meanwhile.
+    pub fn left_behind(&mut self, i: usize, now: Instant) -> bool {
+        let t = &mut self.targets[i];
+        let behind = match (t.down_since, self.max_lag) {
+            (Some(since), Some(lag)) => now.saturating_duration_since(since) >= lag,
+            _ => false,
+        };
+        if behind && !t.left_behind {
+            warn!(
+                endpoint = %t.endpoint.url,
+                "fan-out endpoint down too long; no longer keeping events for it"
+            );
+        }
+        t.left_behind = behind;
+        behind
+    }
+    /// Per-endpoint health for `/status`.
+    pub fn report(&self, now: Instant) -> Value {
+        let items: Vec<Value> = self
+            .targets
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let breaker = match t.open_until {
+                    None => "closed",
+                    Some(_) if self.available(i, now) => "half_open",
+                    Some(_) => "open",
+                };
+                json!({
+                    "url": t.endpoint.url,
+                    "priority": t.endpoint.priority,
+                    "breaker": breaker,
+                    "consecutive_failures": t.failures,
+                    "left_behind": t.left_behind,
+                    "last_error": t.last_error,
+                })
+            })
+            .collect();
+        Value::Array(items)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn endpoints(delivery: Delivery, priorities: &[u32]) -> Endpoints {
+        let mut net = Networking {
+            delivery,
+            breaker_failures: 2,
+            breaker_open_secs: 30,
+            ..Networking::default()
+        };
+        net.endpoints = priorities
+            .iter()
+            .enumerate()
+            .map(|(i, &priority)| Endpoint {
+                url: format!("https://rx{i}.example/ingest"),
+                priority,
+                name: String::new(),
+            })
+            .collect();
+        Endpoints::new(&net)
+    }
+    #[test]
+    fn failover_follows_priority_and_skips_open_breakers_until_trial() {
+        let t0 = Instant::now();
+        let mut eps = endpoints(Delivery::Failover, &[1, 0, 2]);
+        assert_eq!(eps.order(t0), vec![1, 0, 2]);
+        eps.failed(1, "503".into(), t0);
+        assert_eq!(eps.order(t0), vec![1, 0, 2]);
+        eps.failed(1, "503".into(), t0);
+        assert_eq!(eps.order(t0), vec![0, 2]);
+        assert_eq!(eps.report(t0)[1]["breaker"], "open");
+        let later = t0 + Duration::from_secs(30);
+        assert_eq!(eps.order(later), vec![1, 0, 2]);
+        assert_eq!(eps.report(later)[1]["breaker"], "half_open");
+        eps.failed(1, "503".into(), later);
+        assert_eq!(eps.order(later), vec![0, 2]);
+        eps.succeeded(1);
+        assert_eq!(eps.order(later), vec![1, 0, 2]);
+        assert_eq!(eps.report(later)[1]["consecutive_failures"], 0);
+    }
+    #[test]
+    fn fan_out_leaves_an_endpoint_behind_after_the_max_lag() {
+        let t0 = Instant::now();
+        let mut eps = endpoints(Delivery::FanOut, &[0, 0]);
+        eps.max_lag = Some(Duration::from_secs(60));
+        eps.failed(1, "503".into(), t0);
+        assert!(!eps.left_behind(1, t0 + Duration::from_secs(600)));
+        eps.failed(1, "503".into(), t0);
+        eps.failed(1, "503".into(), t0 + Duration::from_secs(30));
+        assert!(!eps.left_behind(1, t0 + Duration::from_secs(59)));
+        assert!(eps.left_behind(1, t0 + Duration::from_secs(60)));
+        assert_eq!(eps.report(t0)[1]["left_behind"], true);
+        assert!(!eps.left_behind(0, t0 + Duration::from_secs(60)));
+        eps.succeeded(1);
+        assert!(!eps.left_behind(1, t0 + Duration::from_secs(600)));
+        eps.max_lag = None;
+        eps.failed(1, "503".into(), t0);
+        eps.failed(1, "503".into(), t0);
+        assert!(!eps.left_behind(1, t0 + Duration::from_secs(600)));
+    }
+    #[test]
+    fn round_robin_rotates_within_the_best_tier() {
+        let t0 = Instant::now();
+        let mut eps = endpoints(Delivery::RoundRobin, &[0, 0, 1]);
+        assert_eq!(eps.order(t0), vec![0, 1, 2]);
+        assert_eq!(eps.order(t0), vec![1, 0, 2]);
+        assert_eq!(eps.order(t0), vec![0, 1, 2]);
+        eps.failed(0, "timeout".into(), t0);
+        eps.failed(0, "timeout".into(), t0);
+        assert_eq!(eps.order(t0), vec![1, 2]);
+        let single = endpoints(Delivery::Failover, &[]);
+        assert_eq!(single.len(), 1);
+        assert_eq!(single.url(0), "https://127.0.0.1:8443/ingest");
+    }
+}
@@ -1,75 +1,588 @@
 pub mod client;
+pub mod endpoints;
+#[cfg(feature = "networking")]
+pub mod enroll;
 pub mod mtls;
 pub mod queue;
+#[cfg(feature = "networking")]
+pub mod remote;
 #[cfg(feature = "status")]
-pub mod status {
-    use anyhow::Result;
-    use hyper::{Body, Method, Request, Response};
-    use hyper_util::rt::TokioIo;
-    use tokio::net::TcpListener;
-    use tracing::info;
-    pub fn spawn_status_server(port: u16) -> Result<()> {
-        tokio::spawn(async move {
-            let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
-            let listener = TcpListener::bind(addr).await.expect("bind");
-            info!(%addr,"status server listening");
-            loop {
-                let (stream, _) = listener.accept().await.expect("accept");
-                let io = TokioIo::new(stream);
-                tokio::spawn(async move {
-                    let conn = hyper::server::conn::http1::Builder::new()
-                        .serve_connection(io, hyper::service::service_fn(handler));
-                    if let Err(e) = conn.await {
-                        tracing::warn!(error=?e,"status conn error");
-                    }
-                });
-            }
-        });
-        Ok(())
-    }
-    async fn handler(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
-        match (req.method(), req.uri().path()) {
-            (&Method::GET, "/healthz") => Ok(Response::new(Body::from("ok"))),
-            (&Method::GET, "/metrics") => Ok(Response::new(Body::from("rea_up 1\n"))),
-            _ => Ok(Response::builder().status(404).body(Body::empty()).unwrap()),
-        }
-    }
-}
+pub mod status;
 pub mod modu {
-    use super::{client::NetClient, queue::DiskQueue};
-    use crate::config::AgentConfig;
-    use anyhow::Result;
+    use super::{
+        client::{expiring_in_days, NetClient},
+        endpoints::Endpoints,
+        queue::{item_name, DiskQueue},
+    };
+    use crate::config::{AgentConfig, Delivery, Networking};
+    use crate::shutdown::Shutdown;
+    use anyhow::{anyhow, bail, Result};
+    use serde_json::json;
+    use std::time::{Duration, Instant};
+    use time::{format_description::well_known::Rfc3339, OffsetDateTime};
     use tokio::sync::mpsc::{self, Sender};
+    use tokio::sync::watch;
     use tracing::{info, warn};
+    const FINAL_FLUSH: Duration = Duration::from_secs(5);
+    /// How often the sender looks for replaced TLS files between batches.
+    const TLS_CHECK: Duration = Duration::from_secs(5);
+    const EXPIRY_WARN_EVERY: Duration = Duration::from_secs(24 * 3600);
+    /// Spawns the sender that spools events to the disk queue and posts batches.
+    /// On shutdown it keeps spooling until every `Sender` is dropped, so nothing in
+    /// flight is lost, then makes one bounded attempt to empty the queue. Dropping
+    /// every `Sender` before shutdown stops it the same way.
     #[cfg(feature = "networking")]
-    pub async fn maybe_spawn_network_sender(cfg: &AgentConfig) -> Result<Option<Sender<Vec<u8>>>> {
+    pub async fn maybe_spawn_network_sender(
+        cfg: &AgentConfig,
+        mut cfg_rx: watch::Receiver<AgentConfig>,
+        shutdown: Shutdown,
+    ) -> Result<Option<Sender<Vec<u8>>>> {
         if !cfg.networking.enabled {
             return Ok(None);
         }
         let (tx, mut rx) = mpsc::channel::<Vec<u8>>(1024);
         let mut queue =
             DiskQueue::open(&cfg.networking.queue_dir, cfg.networking.queue_max_bytes).await?;
-        let client = NetClient::new(cfg).await?;
-        let endpoint = cfg.networking.endpoint.clone();
-        let flush_every = std::time::Duration::from_millis(cfg.networking.flush_interval_ms);
-        let compression = cfg.networking.compression.clone();
-        let retry_budget = cfg.networking.retry_budget;
-        tokio::spawn(async move {
-            let mut budget = retry_budget;
+        let mut client = NetClient::new(cfg).await?;
+        let mut net = cfg.networking.clone();
+        let mut endpoints = Endpoints::new(&net);
+        let mut current = cfg.clone();
+        let stop = shutdown.clone();
+        shutdown.spawn(async move {
+            let mut budget = net.retry_budget;
+            let mut tls_checked = Instant::now();
+            let mut expiry_warned = None;
+            let mut tick = flush_timer(net.flush_interval_ms);
+            crate::health::client_cert(client.not_after(), false);
+            warn_if_expiring(&client, &net, &mut expiry_warned);
             loop {
                 tokio::select! {
-                  Some(line)=rx.recv()=>{ if let Err(e)=queue.enqueue(line).await{ warn!(error=?e,"enqueue failed"); } }
-                  _=tokio::time::sleep(flush_every)=>{ let mut batch=Vec::new(); let mut bytes:usize=0;
-                    while let Ok(Some(item))=queue.peek_oldest().await{ if bytes+item.len()>512*1024{ break; } bytes+=item.len(); batch.push(item); let _=queue.pop_oldest().await; }
-                    if batch.is_empty(){ continue; }
-                    match client.post_ndjson(&endpoint,batch,&compression).await{
-                      Ok(_)=>{ budget=retry_budget; info!("batch delivered"); }
-                      Err(e)=>{ warn!(error=?e,"post failed"); budget=budget.saturating_sub(1); if budget==0{ warn!("retry budget exhausted; dropping until next cycle"); } }
+                  line=rx.recv()=>{ let Some(line)=line else{ break }; if let Err(e)=queue.enqueue(line).await{ warn!(error=?e,"enqueue failed"); } report_queue(&queue); }
+                  Ok(())=cfg_rx.changed()=>{
+                    let next=cfg_rx.borrow_and_update().clone();
+                    current=next.clone();
+                    if next.networking==net{ continue; }
+                    match NetClient::new(&next).await{ Ok(c)=>{ client=c; expiry_warned=None; crate::health::client_cert(client.not_after(),false); } Err(e)=>warn!(error=?e,"keeping previous HTTPS client") }
+                    if (&next.networking.queue_dir,next.networking.queue_max_bytes)!=(&net.queue_dir,net.queue_max_bytes){
+                      match DiskQueue::open(&next.networking.queue_dir,next.networking.queue_max_bytes).await{
+                        Ok(mut moved)=>{
+                          let keys:Vec<&str>=(0..endpoints.len()).map(|i| endpoints.key(i)).collect();
+                          if let Err(e)=queue.move_into(&mut moved,&keys).await{ warn!(error=?e,"moving the queue stopped early; the rest stays in the previous queue dir"); }
+                          queue=moved;
+                        }
+                        Err(e)=>warn!(error=?e,"keeping previous queue dir"),
+                      }
+                    }
+                    budget=next.networking.retry_budget;
+                    if next.networking.flush_interval_ms!=net.flush_interval_ms{ tick=flush_timer(next.networking.flush_interval_ms); }
+                    endpoints=Endpoints::new(&next.networking);
+                    net=next.networking;
+                    info!(endpoints=endpoints.len(),delivery=?net.delivery,"network sender reconfigured");
+                  }
+                  _=stop.cancelled()=>break,
+                  _=tick.tick()=>{
+                    if tls_checked.elapsed()>=TLS_CHECK{
+                      tls_checked=Instant::now();
+                      if rotate_tls(&mut client,&current,&mut queue).await{ expiry_warned=None; }
+                      warn_if_expiring(&client,&net,&mut expiry_warned);
+                    }
+                    match deliver(&client,&queue,&net,&mut endpoints).await{
+                      Ok(0)=>{}
+                      Ok(n)=>{ budget=net.retry_budget; crate::health::delivered(None); info!(events=n,"batch delivered"); }
+                      Err(e)=>{ warn!(error=?e,"post failed"); crate::health::delivered(Some(format!("{e:#}"))); budget=budget.saturating_sub(1); if budget==0{ warn!("retry budget exhausted; events stay queued on disk"); } }
                     }
                   }
                 }
             }
+            while let Some(line)=rx.recv().await{ if let Err(e)=queue.enqueue(line).await{ warn!(error=?e,"enqueue failed"); } }
+            report_queue(&queue);
+            let flush=async{ loop{ match deliver(&client,&queue,&net,&mut endpoints).await{ Ok(0)=>break Ok(()), Ok(_)=>{} Err(e)=>break Err(e) } } };
+            match tokio::time::timeout(FINAL_FLUSH,flush).await{
+              Ok(Ok(()))=>info!("queue flushed before shutdown"),
+              Ok(Err(e))=>warn!(error=?e,"final flush failed; events stay queued on disk"),
+              Err(_)=>warn!("final flush timed out; events stay queued on disk"),
+            }
         });
         Ok(Some(tx))
     }
+    /// Paces batches: a slow post delays the next tick instead of bunching up
+    /// missed ones, and other wakeups (events, reloads) leave it alone.
+    #[cfg(feature = "networking")]
+    fn flush_timer(ms: u64) -> tokio::time::Interval {
+        let period = Duration::from_millis(ms.max(1));
+        let mut tick = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        tick
+    }
+    /// Swaps in a client built from replaced TLS files, so the next batch already
+    /// uses them, and queues a `tls_rotated` event. Returns whether it rotated.
+    #[cfg(feature = "networking")]
+    async fn rotate_tls(client: &mut NetClient, cfg: &AgentConfig, queue: &mut DiskQueue) -> bool {
+        let previous = client.not_after();
+        match client.refresh_tls(cfg) {
+            Ok(false) => false,
+            Ok(true) => {
+                let not_after = client.not_after();
+                info!(?not_after, "client TLS identity rotated");
+                crate::health::client_cert(not_after, true);
+                let iso = |t: Option<OffsetDateTime>| t.and_then(|t| t.format(&Rfc3339).ok());
+                let body =
+                    json!({ "not_after": iso(not_after), "previous_not_after": iso(previous) });
+                if let Some(line) = crate::collectors::envelope_line(cfg, "tls_rotated", body) {
+                    crate::metrics::event_emitted("tls_rotated");
+                    crate::health::record_event(&line);
+                    if let Err(e) = queue.enqueue(line).await {
+                        warn!(error=?e, "enqueue failed");
+                    }
+                }
+                true
+            }
+            Err(e) => {
+                warn!(error=?e, "TLS files changed but do not load; keeping previous client");
+                false
+            }
+        }
+    }
+    /// Warns about a client certificate close to expiry, at most once a day.
+    #[cfg(feature = "networking")]
+    fn warn_if_expiring(client: &NetClient, net: &Networking, warned: &mut Option<Instant>) {
+        let now = OffsetDateTime::now_utc();
+        let Some(days_left) = expiring_in_days(client.not_after(), net.cert_expiry_warn_days, now)
+        else {
+            return;
+        };
+        if warned.is_some_and(|at| at.elapsed() < EXPIRY_WARN_EVERY) {
+            return;
+        }
+        *warned = Some(Instant::now());
+        warn!(days_left, not_after=?client.not_after(), "client certificate expires soon");
+    }
+    /// Sends queued events the way `net.delivery` says and publishes the
+    /// per-endpoint state; returns how many events were delivered.
+    #[cfg(feature = "networking")]
+    async fn deliver(
+        client: &NetClient,
+        queue: &DiskQueue,
+        net: &Networking,
+        endpoints: &mut Endpoints,
+    ) -> Result<usize> {
+        let res = match net.delivery {
+            Delivery::FanOut => fan_out(client, queue, net, endpoints).await,
+            Delivery::Failover | Delivery::RoundRobin => {
+                deliver_one(client, queue, net, endpoints).await
+            }
+        };
+        crate::health::endpoints(endpoints.report(Instant::now()));
+        res
+    }
+    /// Posts the oldest batch to the first endpoint that accepts it and removes
+    /// it from the queue only then.
+    #[cfg(feature = "networking")]
+    async fn deliver_one(
+        client: &NetClient,
+        queue: &DiskQueue,
+        net: &Networking,
+        endpoints: &mut Endpoints,
+    ) -> Result<usize> {
+        let batch = queue
+            .peek_batch(net.batch_max_events, net.batch_max_bytes)
+            .await?;
+        if batch.is_empty() {
+            return Ok(0);
+        }
+        let now = Instant::now();
+        let order = endpoints.order(now);
+        if order.is_empty() {
+            bail!("every endpoint's circuit breaker is open");
+        }
+        let (paths, lines): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+        let mut last = None;
+        for i in order {
+            match post(client, endpoints.url(i), lines.clone(), net).await {
+                Ok(()) => {
+                    endpoints.succeeded(i);
+                    queue.remove(&paths).await?;
+                    report_queue(queue);
+                    return Ok(paths.len());
+                }
+                Err(e) => {
+                    warn!(endpoint=%endpoints.url(i), error=?e, "post failed");
+                    endpoints.failed(i, format!("{e:#}"), now);
+                    last = Some(e);
+                }
+            }
+        }
+        Err(last.unwrap_or_else(|| anyhow!("no endpoint accepted the batch")))
+    }
+    /// Posts the next batch to every endpoint from its own cursor, so one that is
+    /// down catches up later without holding back the others. Items leave the
+    /// queue once every endpoint not left behind has received them.
+    #[cfg(feature = "networking")]
+    async fn fan_out(
+        client: &NetClient,
+        queue: &DiskQueue,
+        net: &Networking,
+        endpoints: &mut Endpoints,
+    ) -> Result<usize> {
+        let now = Instant::now();
+        let (mut sent, mut errors) = (0, Vec::new());
+        let mut cursors = Vec::with_capacity(endpoints.len());
+        for i in 0..endpoints.len() {
+            let mut cursor = queue.cursor(endpoints.key(i)).await?;
+            let behind = endpoints.left_behind(i, now);
+            if endpoints.available(i, now) {
+                let batch = queue
+                    .peek_batch_after(cursor.as_deref(), net.batch_max_events, net.batch_max_bytes)
+                    .await?;
+                if let Some((newest, _)) = batch.last() {
+                    let newest = item_name(newest).to_string();
+                    let n = batch.len();
+                    let lines = batch.into_iter().map(|(_, data)| data).collect();
+                    match post(client, endpoints.url(i), lines, net).await {
+                        Ok(()) => {
+                            endpoints.succeeded(i);
+                            queue.set_cursor(endpoints.key(i), &newest).await?;
+                            cursor = Some(newest);
+                            sent = sent.max(n);
+                        }
+                        Err(e) => {
+                            warn!(endpoint=%endpoints.url(i), error=?e, "post failed");
+                            endpoints.failed(i, format!("{e:#}"), now);
+                            errors.push(format!("{}: {e:#}", endpoints.url(i)));
+                        }
+                    }
+                }
+            }
+            if !behind {
+                cursors.push(cursor);
+            }
+        }
+        // `None` sorts first: nothing is trimmed until every endpoint has a cursor.
+        // Endpoints left behind do not count, so one that stays down does not
+        // pin the queue for the others.
+        let oldest = cursors.iter().map(Option::as_deref).min().flatten();
+        if let Some(oldest) = oldest {
+            if queue.remove_through(oldest).await? > 0 {
+                report_queue(queue);
+            }
+        }
+        if sent == 0 && !errors.is_empty() {
+            bail!("{}", errors.join("; "));
+        }
+        Ok(sent)
+    }
+    #[cfg(feature = "networking")]
+    async fn post(
+        client: &NetClient,
+        url: &str,
+        lines: Vec<Vec<u8>>,
+        net: &Networking,
+    ) -> Result<()> {
+        let started = Instant::now();
+        let res = client.post_ndjson(url, lines, net.compression).await;
+        crate::metrics::post_finished(started.elapsed(), res.is_ok());
+        res
+    }
+    #[cfg(feature = "networking")]
+    fn report_queue(queue: &DiskQueue) {
+        let (depth, bytes) = queue.size();
+        crate::metrics::queue_size(depth, bytes);
+        crate::health::queue_size(depth, bytes);
+    }
+}
+#[cfg(all(test, feature = "networking"))]
+mod tests {
+    use super::{modu::maybe_spawn_network_sender, queue::DiskQueue};
+    use crate::{
+        config::{AgentConfig, Compression, Delivery, Endpoint},
+        shutdown::Shutdown,
+        test_support::read_request,
+    };
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use tokio::{
+        io::AsyncWriteExt,
+        net::TcpListener,
+        sync::{watch, Mutex},
+    };
+    /// Records posted lines while `up`; answers 503 otherwise.
+    async fn ingest_stub(
+        listener: TcpListener,
+        lines: Arc<Mutex<Vec<String>>>,
+        up: Arc<AtomicBool>,
+    ) {
+        loop {
+            let (mut s, _) = listener.accept().await.unwrap();
+            let (lines, up) = (lines.clone(), up.clone());
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                while let Some((_, body)) = read_request(&mut s, &mut buf).await {
+                    if !up.load(Ordering::SeqCst) {
+                        s.write_all(
+                            b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n",
+                        )
+                        .await
+                        .unwrap();
+                        continue;
+                    }
+                    lines
+                        .lock()
+                        .await
+                        .extend(String::from_utf8(body).unwrap().lines().map(String::from));
+                    s.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                        .await
+                        .unwrap();
+                }
+            });
+        }
+    }
+    #[tokio::test]
+    async fn queued_events_survive_shutdown_and_are_sent_after_restart() {
+        let dir = std::env::temp_dir().join(format!("rea-q-{}", uuid::Uuid::new_v4()));
+        let dead = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dead_addr = dead.local_addr().unwrap();
+        drop(dead);
+        let mut cfg = AgentConfig::default();
+        cfg.networking.enabled = true;
+        cfg.networking.endpoint = format!("http://{dead_addr}/ingest");
+        cfg.networking.queue_dir = dir.clone();
+        cfg.networking.flush_interval_ms = 20;
+        cfg.networking.compression = Compression::None;
+        let (_cfg_tx, cfg_rx) = watch::channel(cfg.clone());
+        let shutdown = Shutdown::default();
+        let tx = maybe_spawn_network_sender(&cfg, cfg_rx.clone(), shutdown.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        shutdown.trigger();
+        for i in 0..20 {
+            tx.send(format!("{{\"n\":{i}}}\n").into_bytes())
+                .await
+                .unwrap();
+        }
+        drop(tx);
+        assert!(shutdown.drain(Duration::from_secs(10)).await);
+        let queue = DiskQueue::open(&dir, u64::MAX).await.unwrap();
+        assert_eq!(queue.stats().await.unwrap().0, 20);
+        assert_eq!(queue.size().0, 20);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        cfg.networking.endpoint = format!("http://{}/ingest", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let up = Arc::new(AtomicBool::new(true));
+        tokio::spawn(ingest_stub(listener, received.clone(), up));
+        let shutdown = Shutdown::default();
+        let tx = maybe_spawn_network_sender(&cfg, cfg_rx, shutdown.clone())
+            .await
+            .unwrap();
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        while received.lock().await.len() < 20 && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        shutdown.trigger();
+        drop(tx);
+        assert!(shutdown.drain(Duration::from_secs(10)).await);
+        let mut got = received.lock().await.clone();
+        got.sort_by_key(|l| l[5..l.len() - 1].parse::<u32>().unwrap());
+        let want: Vec<String> = (0..20).map(|i| format!("{{\"n\":{i}}}")).collect();
+        assert_eq!(got, want);
+        assert_eq!(queue.stats().await.unwrap().0, 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    /// Items in the queue directory, counted without racing the sender's removals.
+    fn queued(dir: &std::path::Path) -> usize {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".ndjson"))
+            .count()
+    }
+    async fn wait_for(what: impl Fn() -> bool) {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        while !what() && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+    #[tokio::test]
+    async fn sender_stops_and_flushes_once_its_channel_closes() {
+        let dir = std::env::temp_dir().join(format!("rea-q-{}", uuid::Uuid::new_v4()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut cfg = AgentConfig::default();
+        cfg.networking.enabled = true;
+        cfg.networking.endpoint = format!("http://{}/ingest", listener.local_addr().unwrap());
+        cfg.networking.queue_dir = dir.clone();
+        cfg.networking.flush_interval_ms = 60_000;
+        cfg.networking.compression = Compression::None;
+        let received = Arc::new(Mutex::new(Vec::new()));
+        tokio::spawn(ingest_stub(
+            listener,
+            received.clone(),
+            Arc::new(AtomicBool::new(true)),
+        ));
+        let (_cfg_tx, cfg_rx) = watch::channel(cfg.clone());
+        let shutdown = Shutdown::default();
+        let tx = maybe_spawn_network_sender(&cfg, cfg_rx, shutdown.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        tx.send(b"{\"n\":0}\n".to_vec()).await.unwrap();
+        drop(tx);
+        assert!(shutdown.drain(Duration::from_secs(10)).await);
+        assert_eq!(*received.lock().await, ["{\"n\":0}"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[tokio::test]
+    async fn flushes_keep_their_pace_under_a_steady_stream_of_events() {
+        let dir = std::env::temp_dir().join(format!("rea-q-{}", uuid::Uuid::new_v4()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut cfg = AgentConfig::default();
+        cfg.networking.enabled = true;
+        cfg.networking.endpoint = format!("http://{}/ingest", listener.local_addr().unwrap());
+        cfg.networking.queue_dir = dir.clone();
+        cfg.networking.flush_interval_ms = 200;
+        cfg.networking.compression = Compression::None;
+        let received = Arc::new(Mutex::new(Vec::new()));
+        tokio::spawn(ingest_stub(
+            listener,
+            received.clone(),
+            Arc::new(AtomicBool::new(true)),
+        ));
+        let (cfg_tx, cfg_rx) = watch::channel(cfg.clone());
+        let shutdown = Shutdown::default();
+        let tx = maybe_spawn_network_sender(&cfg, cfg_rx, shutdown.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        // Events arrive faster than the flush interval; batches must still go out.
+        for i in 0..20 {
+            tx.send(format!("{{\"n\":{i}}}\n").into_bytes())
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(received.lock().await.len() >= 10);
+        cfg.networking.flush_interval_ms = 20;
+        cfg_tx.send(cfg).unwrap();
+        for i in 20..30 {
+            tx.send(format!("{{\"n\":{i}}}\n").into_bytes())
+                .await
+                .unwrap();
+        }
+        wait_for(|| received.try_lock().is_ok_and(|l| l.len() == 30)).await;
+        assert_eq!(received.lock().await.len(), 30);
+        shutdown.trigger();
+        drop(tx);
+        assert!(shutdown.drain(Duration::from_secs(10)).await);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[tokio::test]
+    async fn fan_out_keeps_events_for_a_down_endpoint_until_it_catches_up() {
+        let dir = std::env::temp_dir().join(format!("rea-q-{}", uuid::Uuid::new_v4()));
+        let mut cfg = AgentConfig::default();
+        cfg.networking.enabled = true;
+        cfg.networking.queue_dir = dir.clone();
+        cfg.networking.flush_interval_ms = 20;
+        cfg.networking.compression = Compression::None;
+        cfg.networking.delivery = Delivery::FanOut;
+        cfg.networking.breaker_failures = 1;
+        cfg.networking.breaker_open_secs = 1;
+        let mut receivers = Vec::new();
+        for name in ["primary", "archive"] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            cfg.networking.endpoints.push(Endpoint {
+                url: format!("http://{}/ingest", listener.local_addr().unwrap()),
+                priority: 0,
+                name: name.into(),
+            });
+            let (lines, up) = (
+                Arc::new(Mutex::new(Vec::new())),
+                Arc::new(AtomicBool::new(true)),
+            );
+            tokio::spawn(ingest_stub(listener, lines.clone(), up.clone()));
+            receivers.push((lines, up));
+        }
+        let (primary, archive) = (&receivers[0], &receivers[1]);
+        archive.1.store(false, Ordering::SeqCst);
+        let (_cfg_tx, cfg_rx) = watch::channel(cfg.clone());
+        let shutdown = Shutdown::default();
+        let tx = maybe_spawn_network_sender(&cfg, cfg_rx, shutdown.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        for i in 0..10 {
+            tx.send(format!("{{\"n\":{i}}}\n").into_bytes())
+                .await
+                .unwrap();
+        }
+        let want: Vec<String> = (0..10).map(|i| format!("{{\"n\":{i}}}")).collect();
+        let queue = DiskQueue::open(&dir, u64::MAX).await.unwrap();
+        wait_for(|| primary.0.try_lock().is_ok_and(|l| l.len() == 10)).await;
+        assert_eq!(*primary.0.lock().await, want);
+        assert_eq!(queue.stats().await.unwrap().0, 10);
+        archive.1.store(true, Ordering::SeqCst);
+        wait_for(|| archive.0.try_lock().is_ok_and(|l| l.len() == 10)).await;
+        assert_eq!(*archive.0.lock().await, want);
+        wait_for(|| queued(&dir) == 0).await;
+        assert_eq!(queued(&dir), 0);
+        assert_eq!(primary.0.lock().await.len(), 10);
+        shutdown.trigger();
+        drop(tx);
+        assert!(shutdown.drain(Duration::from_secs(10)).await);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[tokio::test]
+    async fn fan_out_stops_holding_events_for_an_endpoint_left_behind() {
+        let dir = std::env::temp_dir().join(format!("rea-q-{}", uuid::Uuid::new_v4()));
+        let mut cfg = AgentConfig::default();
+        cfg.networking.enabled = true;
+        cfg.networking.queue_dir = dir.clone();
+        cfg.networking.flush_interval_ms = 20;
+        cfg.networking.compression = Compression::None;
+        cfg.networking.delivery = Delivery::FanOut;
+        cfg.networking.breaker_failures = 1;
+        cfg.networking.breaker_open_secs = 60;
+        cfg.networking.fan_out_max_lag_secs = 1;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dead = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        for (name, addr) in [
+            ("primary", listener.local_addr().unwrap()),
+            ("archive", dead.local_addr().unwrap()),
+        ] {
+            cfg.networking.endpoints.push(Endpoint {
+                url: format!("http://{addr}/ingest"),
+                priority: 0,
+                name: name.into(),
+            });
+        }
+        drop(dead);
+        let received = Arc::new(Mutex::new(Vec::new()));
+        tokio::spawn(ingest_stub(
+            listener,
+            received.clone(),
+            Arc::new(AtomicBool::new(true)),
+        ));
+        let (_cfg_tx, cfg_rx) = watch::channel(cfg.clone());
+        let shutdown = Shutdown::default();
+        let tx = maybe_spawn_network_sender(&cfg, cfg_rx, shutdown.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        for i in 0..10 {
+            tx.send(format!("{{\"n\":{i}}}\n").into_bytes())
+                .await
+                .unwrap();
+        }
+        wait_for(|| received.try_lock().is_ok_and(|l| l.len() == 10)).await;
+        assert_eq!(received.lock().await.len(), 10);
+        wait_for(|| queued(&dir) == 0).await;
+        assert_eq!(queued(&dir), 0);
+        shutdown.trigger();
+        drop(tx);
+        assert!(shutdown.drain(Duration::from_secs(10)).await);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,41 +1,199 @@
-use anyhow::{Context, Result};
+use crate::config::{non_empty, resolve_secret, AgentConfig, Proxy};
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use reqwest::{Certificate, Client, Identity};
-use std::{fs, path::Path};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use time::OffsetDateTime;
+#[cfg(feature = "networking")]
+use tls_keys::{read_certs, read_pkcs12, read_private_key};
+/// Where the password of an encrypted key or bundle is configured.
+const PASSWORD_SOURCE: &str = "networking.key_password";
 pub struct TlsMaterials {
     pub ca: Option<Certificate>,
     pub identity: Option<Identity>,
+    /// Expiry of the client certificate, when there is one.
+    pub not_after: Option<OffsetDateTime>,
 }
-pub fn load_tls(
-    ca: Option<&Path>,
-    cert: Option<&Path>,
-    key: Option<&Path>,
-) -> Result<TlsMaterials> {
-    let ca = if let Some(p) = ca {
-        let pem = fs::read(p).with_context(|| format!("reading CA {}", p.display()))?;
-        Some(Certificate::from_pem(&pem).context("parsing CA PEM")?)
-    } else {
-        None
+fn pem_block(label: &str, der: &[u8]) -> String {
+    let b64 = STANDARD.encode(der);
+    let mut out = format!("-----BEGIN {label}-----\n");
+    for line in b64.as_bytes().chunks(64) {
+        out.push_str(&String::from_utf8_lossy(line));
+        out.push('\n');
+    }
+    out.push_str(&format!("-----END {label}-----\n"));
+    out
+}
+/// Re-encodes the chain and key as the clean PEM `Identity::from_pem` expects.
+fn identity(certs: &[CertificateDer], key: &PrivateKeyDer) -> Result<Identity> {
+    let mut pem = String::new();
+    for c in certs {
+        pem.push_str(&pem_block("CERTIFICATE", c));
+    }
+    let label = match key {
+        PrivateKeyDer::Pkcs1(_) => "RSA PRIVATE KEY",
+        PrivateKeyDer::Sec1(_) => "EC PRIVATE KEY",
+        _ => "PRIVATE KEY",
     };
-    let identity = match (cert, key) {
-        (Some(cp), Some(kp)) => {
-            let mut pem = Vec::new();
-            pem.extend(
-                fs::read(cp).with_context(|| format!("reading client cert {}", cp.display()))?,
-            );
-            pem.extend(b"\n");
-            pem.extend(
-                fs::read(kp).with_context(|| format!("reading client key {}", kp.display()))?,
-            );
-            Some(Identity::from_pem(&pem).context("parsing client identity PEM (PKCS8)")?)
+    pem.push_str(&pem_block(label, key.secret_der()));
+    Identity::from_pem(pem.as_bytes()).context("building client identity")
+}
+#[cfg(feature = "networking")]
+fn der_not_after(der: &[u8]) -> Option<OffsetDateTime> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    Some(cert.validity().not_after.to_datetime())
+}
+/// TLS files in effect: the configured ones, or the enrolled identity (and its
+/// CA when no `ca_cert` is set) once enrollment has produced a certificate.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TlsPaths {
+    pub ca: Option<PathBuf>,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub pkcs12: Option<PathBuf>,
+    /// Secret reference for an encrypted key or the bundle.
+    pub password: Option<String>,
+}
+impl TlsPaths {
+    pub fn for_config(cfg: &AgentConfig) -> Self {
+        let (net, enroll) = (&cfg.networking, &cfg.enrollment);
+        let owned = |p: Option<&Path>| p.map(Path::to_path_buf);
+        let enrolled = enroll.enabled && enroll.cert_path().is_file();
+        let ca = match non_empty(&net.ca_cert) {
+            Some(p) => Some(p.to_path_buf()),
+            None => Some(enroll.ca_path()).filter(|p| enroll.enabled && p.is_file()),
+        };
+        if enrolled {
+            Self {
+                ca,
+                cert: Some(enroll.cert_path()),
+                key: Some(enroll.key_path()),
+                ..Self::default()
+            }
+        } else {
+            Self {
+                ca,
+                cert: owned(non_empty(&net.client_cert)),
+                key: owned(non_empty(&net.client_key)),
+                pkcs12: owned(non_empty(&net.client_pkcs12)),
+                password: net.key_password.clone().filter(|p| !p.is_empty()),
+            }
         }
-        _ => None,
-    };
-    Ok(TlsMaterials { ca, identity })
+    }
+    /// Current contents of the files, to notice when any of them is replaced.
+    pub fn snapshot(&self) -> Vec<Option<Vec<u8>>> {
+        [&self.ca, &self.cert, &self.key, &self.pkcs12]
+            .into_iter()
+            .map(|p| p.as_ref().and_then(|p| fs::read(p).ok()))
+            .collect()
+    }
+    #[cfg(feature = "networking")]
+    pub fn load(&self) -> Result<TlsMaterials> {
+        let password = match self.password.as_deref() {
+            Some(r) => Some(resolve_secret(r).context("networking.key_password")?),
+            None => None,
+        };
+        let ca = match &self.ca {
+            Some(p) => {
+                let pem = fs::read(p).with_context(|| format!("reading CA {}", p.display()))?;
+                Some(Certificate::from_pem(&pem).context("parsing CA PEM")?)
+            }
+            None => None,
+        };
+        let chain = match (&self.pkcs12, &self.cert, &self.key) {
+            (Some(bundle), _, _) => {
+                Some(read_pkcs12(bundle, password.as_deref(), PASSWORD_SOURCE)?)
+            }
+            (None, Some(cert), Some(key)) => Some((
+                read_certs(cert)?,
+                read_private_key(key, password.as_deref(), PASSWORD_SOURCE)?,
+            )),
+            _ => None,
+        };
+        let not_after = chain
+            .as_ref()
+            .and_then(|(certs, _)| der_not_after(certs.first()?));
+        let identity = match chain {
+            Some((certs, key)) => Some(identity(&certs, &key)?),
+            None => None,
+        };
+        Ok(TlsMaterials {
+            ca,
+            identity,
+            not_after,
+        })
+    }
+}
+/// Client for talking to the receiver with the TLS settings in `cfg`.
+#[cfg(feature = "networking")]
+pub fn client_for(cfg: &AgentConfig) -> Result<Client> {
+    build_client(&TlsPaths::for_config(cfg).load()?, &cfg.networking.proxy)
+}
+/// Expiry of the first certificate in a PEM file.
+#[cfg(feature = "networking")]
+pub fn cert_not_after(path: &Path) -> Result<OffsetDateTime> {
+    let pem = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    let (_, pem) = x509_parser::pem::parse_x509_pem(&pem)
+        .map_err(|e| anyhow!("parsing {}: {e}", path.display()))?;
+    der_not_after(&pem.contents).with_context(|| format!("parsing {}", path.display()))
 }
-pub fn build_client(tls: &TlsMaterials) -> Result<Client> {
+/// The configured proxy or, without a `url`, those named by `HTTPS_PROXY`,
+/// `HTTP_PROXY` and `ALL_PROXY` (either case) in `env`, minus `NO_PROXY`.
+fn proxies(p: &Proxy, env: &dyn Fn(&str) -> Option<String>) -> Result<Vec<reqwest::Proxy>> {
+    if p.url == "direct" {
+        return Ok(Vec::new());
+    }
+    if p.url.is_empty() {
+        let var = |name: &str| {
+            env(name)
+                .or_else(|| env(&name.to_ascii_lowercase()))
+                .filter(|v| !v.is_empty())
+        };
+        let no_proxy = var("NO_PROXY").and_then(|v| reqwest::NoProxy::from_string(&v));
+        let mut out = Vec::new();
+        if let Some(url) = var("HTTPS_PROXY") {
+            out.push(reqwest::Proxy::https(url).context("HTTPS_PROXY")?);
+        }
+        if let Some(url) = var("HTTP_PROXY") {
+            out.push(reqwest::Proxy::http(url).context("HTTP_PROXY")?);
+        }
+        if let Some(url) = var("ALL_PROXY") {
+            out.push(reqwest::Proxy::all(url).context("ALL_PROXY")?);
+        }
+        return Ok(out
+            .into_iter()
+            .map(|p| p.no_proxy(no_proxy.clone()))
+            .collect());
+    }
+    let mut proxy =
+        reqwest::Proxy::all(&p.url).with_context(|| format!("networking.proxy.url {}", p.url))?;
+    if !p.username.is_empty() {
+        let password = match p.password.as_deref().filter(|r| !r.is_empty()) {
+            Some(r) => resolve_secret(r).context("networking.proxy.password")?,
+            None => String::new(),
+        };
+        proxy = proxy.basic_auth(&p.username, &password);
+    }
+    if !p.no_proxy.is_empty() {
+        proxy = proxy.no_proxy(reqwest::NoProxy::from_string(&p.no_proxy.join(",")));
+    }
+    Ok(vec![proxy])
+}
+pub fn build_client(tls: &TlsMaterials, proxy_cfg: &Proxy) -> Result<Client> {
+    build_client_with_env(tls, proxy_cfg, &|name| std::env::var(name).ok())
+}
+/// [`build_client`] taking the proxy variables from `env`.
+pub fn build_client_with_env(
+    tls: &TlsMaterials,
+    proxy_cfg: &Proxy,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<Client> {
     let mut b = reqwest::Client::builder()
         .use_rustls_tls()
-        .http2_prior_knowledge(false)
         .http2_adaptive_window(true)
         .tcp_nodelay(true)
         .pool_max_idle_per_host(2);
@@ -45,5 +203,83 @@ pub fn build_client(tls: &TlsMaterials) -> Result<Client> {
     if let Some(id) = &tls.identity {
         b = b.identity(id.clone());
     }
-    Ok(b.build().context("building reqwest client")?)
+    b = b.no_proxy();
+    for p in proxies(proxy_cfg, env)? {
+        b = b.proxy(p);
+    }
+    b.build().context("building reqwest client")
+}
+#[cfg(all(test, feature = "networking"))]
+mod tests {
+    use super::*;
+    use p12_keystore::{KeyStore, KeyStoreEntry, PrivateKeyChain};
+    use pkcs8::{pkcs5::pbes2, LineEnding, PrivateKeyInfo};
+    /// Key formats and their errors are covered in `tls-keys`; this checks what
+    /// the agent adds: secret references, PEM re-encoding and the client build.
+    #[test]
+    fn loaded_identities_re_encode_into_a_client() {
+        let dir = std::env::temp_dir().join(format!("rea-keys-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let ck = rcgen::generate_simple_self_signed(vec!["rea-test".into()]).unwrap();
+        let cert = dir.join("client.crt");
+        fs::write(&cert, ck.cert.pem()).unwrap();
+        let der = ck.key_pair.serialize_der();
+        let info = PrivateKeyInfo::try_from(der.as_slice()).unwrap();
+        let params =
+            pbes2::Parameters::pbkdf2_sha256_aes256cbc(2048, b"rea-salt", &[7; 16]).unwrap();
+        let encrypted = info
+            .encrypt_with_params(params, "hunter2")
+            .unwrap()
+            .to_pem("ENCRYPTED PRIVATE KEY", LineEnding::LF)
+            .unwrap();
+        let secret = |name: &str, value: &str| {
+            fs::write(dir.join(name), format!("{value}\n")).unwrap();
+            format!("file:{}", dir.join(name).display())
+        };
+        let (right, wrong) = (secret("right", "hunter2"), secret("wrong", "hunter3"));
+        let build = |paths: TlsPaths| {
+            paths.load().and_then(|tls| {
+                build_client(&tls, &Proxy::default())?;
+                Ok(tls.not_after)
+            })
+        };
+        let load = |key: &str, password: Option<&str>| {
+            fs::write(dir.join("client.key"), key).unwrap();
+            build(TlsPaths {
+                cert: Some(cert.clone()),
+                key: Some(dir.join("client.key")),
+                password: password.map(String::from),
+                ..TlsPaths::default()
+            })
+        };
+        assert!(load(&ck.key_pair.serialize_pem(), None).unwrap().is_some());
+        load(&pem_block("EC PRIVATE KEY", info.private_key), None).unwrap();
+        load(&encrypted, Some(&right)).unwrap();
+        let rsa_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../tls-keys/testdata");
+        let rsa = build(TlsPaths {
+            cert: Some(rsa_dir.join("rsa.crt")),
+            key: Some(rsa_dir.join("rsa-pkcs1.key")),
+            ..TlsPaths::default()
+        });
+        assert!(rsa.unwrap().is_some());
+        let mut store = KeyStore::new();
+        let p12_cert = p12_keystore::Certificate::from_der(ck.cert.der()).unwrap();
+        let chain = PrivateKeyChain::new(&der, [1u8; 20], [p12_cert]);
+        store.add_entry("client", KeyStoreEntry::PrivateKeyChain(chain));
+        let bundle = dir.join("client.p12");
+        fs::write(&bundle, store.writer("hunter2").write().unwrap()).unwrap();
+        let p12 = |password: &str| {
+            build(TlsPaths {
+                pkcs12: Some(bundle.clone()),
+                password: Some(password.into()),
+                ..TlsPaths::default()
+            })
+        };
+        assert!(p12(&right).unwrap().is_some());
+        let err = |res: Result<Option<OffsetDateTime>>| format!("{:#}", res.unwrap_err());
+        assert!(err(p12(&wrong)).contains("wrong networking.key_password?"));
+        assert!(err(load(&encrypted, None)).contains("set networking.key_password"));
+        assert!(err(load(&encrypted, Some("hunter2"))).contains("env:NAME"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,42 +1,178 @@
 use anyhow::Result;
 use rand::{distributions::Alphanumeric, Rng};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::{fs, io::AsyncWriteExt};
+/// Subdirectory with the per-target positions of fan-out delivery.
+const CURSORS: &str = "cursors";
 pub struct DiskQueue {
     dir: PathBuf,
     cap_bytes: u64,
+    /// Timestamp of the last enqueued name, kept strictly increasing so that
+    /// names sort in enqueue order even within one millisecond.
+    last_ms: i128,
+    /// Items and bytes on disk, kept up to date by every change made through
+    /// this handle so reporting the size needs no directory scan.
+    depth: AtomicU64,
+    bytes: AtomicU64,
 }
 impl DiskQueue {
     pub async fn open(dir: &Path, cap: u64) -> Result<Self> {
         fs::create_dir_all(dir).await?;
-        Ok(Self {
+        let mut q = Self {
             dir: dir.to_path_buf(),
             cap_bytes: cap,
-        })
+            last_ms: 0,
+            depth: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        };
+        let (depth, bytes) = q.stats().await?;
+        q.depth.store(depth, Ordering::Relaxed);
+        q.bytes.store(bytes, Ordering::Relaxed);
+        // Items left by an earlier run may carry timestamps ahead of the clock.
+        if let Some(newest) = q.files().await?.last() {
+            let stamp = item_name(newest).split_once('-').map(|(ms, _)| ms.parse());
+            if let Some(Ok(ms)) = stamp {
+                q.last_ms = ms;
+            }
+        }
+        Ok(q)
     }
     pub async fn enqueue(&mut self, data: Vec<u8>) -> Result<()> {
+        self.enqueue_named(data).await.map(|_| ())
+    }
+    /// `enqueue`, returning the name the item got.
+    async fn enqueue_named(&mut self, data: Vec<u8>) -> Result<String> {
         self.enforce_cap().await?;
-        let name = format!("{}-{}.ndjson", now_ms(), rand_str(6));
-        let p = self.dir.join(name);
+        self.last_ms = now_ms().max(self.last_ms + 1);
+        let name = format!("{}-{}.ndjson", self.last_ms, rand_str(6));
+        let p = self.dir.join(&name);
         let mut f = fs::File::create(&p).await?;
         f.write_all(&data).await?;
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+        Ok(name)
+    }
+    /// Moves every item into `target`, oldest first, together with the cursors
+    /// of the fan-out targets `keys`, which then name the items' new names. A
+    /// target that had received none of the moved items starts without a cursor.
+    pub async fn move_into(&self, target: &mut DiskQueue, keys: &[&str]) -> Result<()> {
+        let mut cursors = Vec::with_capacity(keys.len());
+        for key in keys {
+            cursors.push((*key, self.cursor(key).await?, None));
+        }
+        let moved = async {
+            for f in self.files().await? {
+                let name = target.enqueue_named(fs::read(&f).await?).await?;
+                for (_, old, new) in &mut cursors {
+                    if old.as_deref().is_some_and(|c| item_name(&f) <= c) {
+                        *new = Some(name.clone());
+                    }
+                }
+                self.remove_file(&f).await?;
+            }
+            anyhow::Ok(())
+        }
+        .await;
+        for (key, _, new) in cursors {
+            match new {
+                Some(name) => target.set_cursor(key, &name).await?,
+                None => match fs::remove_file(target.cursor_path(key)).await {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                },
+            }
+        }
+        moved
+    }
+    /// Oldest items, without removing them, up to `max_items` and `max_bytes`
+    /// (but always at least one).
+    pub async fn peek_batch(
+        &self,
+        max_items: usize,
+        max_bytes: usize,
+    ) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+        self.peek_batch_after(None, max_items, max_bytes).await
+    }
+    /// Like `peek_batch`, but only items queued after the one named `cursor`.
+    pub async fn peek_batch_after(
+        &self,
+        cursor: Option<&str>,
+        max_items: usize,
+        max_bytes: usize,
+    ) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+        let files = self
+            .files()
+            .await?
+            .into_iter()
+            .filter(|f| cursor.map_or(true, |c| item_name(f) > c));
+        let (mut out, mut bytes) = (Vec::new(), 0usize);
+        for f in files.take(max_items.max(1)) {
+            let data = fs::read(&f).await?;
+            if !out.is_empty() && bytes + data.len() > max_bytes {
+                break;
+            }
+            bytes += data.len();
+            out.push((f, data));
+        }
+        Ok(out)
+    }
+    pub async fn remove(&self, items: &[PathBuf]) -> Result<()> {
+        for f in items {
+            self.remove_file(f).await?;
+        }
         Ok(())
     }
-    pub async fn peek_oldest(&self) -> Result<Option<Vec<u8>>> {
-        let mut rd = fs::read_dir(&self.dir).await?;
-        let mut files: Vec<PathBuf> = Vec::new();
-        while let Some(e) = rd.next_entry().await? {
-            if e.metadata().await?.is_file() {
-                files.push(e.path());
+    /// Removes every item up to and including the one named `last`.
+    pub async fn remove_through(&self, last: &str) -> Result<usize> {
+        let mut n = 0;
+        for f in self.files().await? {
+            if item_name(&f) > last {
+                break;
             }
+            self.remove_file(&f).await?;
+            n += 1;
         }
-        files.sort();
-        if let Some(f) = files.first() {
-            return Ok(Some(fs::read(f).await?));
+        Ok(n)
+    }
+    /// Name of the last item delivered to the fan-out target `key`, if any.
+    pub async fn cursor(&self, key: &str) -> Result<Option<String>> {
+        match fs::read_to_string(self.cursor_path(key)).await {
+            Ok(name) => Ok(Some(name.trim().to_string()).filter(|n| !n.is_empty())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
         }
-        Ok(None)
     }
-    pub async fn pop_oldest(&self) -> Result<()> {
+    pub async fn set_cursor(&self, key: &str, name: &str) -> Result<()> {
+        let path = self.cursor_path(key);
+        fs::create_dir_all(self.dir.join(CURSORS)).await?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, name).await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+    /// `key` with every byte but ASCII letters, digits, `-` and `_` written as
+    /// `%XX`, so distinct keys never share a file.
+    fn cursor_path(&self, key: &str) -> PathBuf {
+        let mut safe = String::with_capacity(key.len());
+        for b in key.bytes() {
+            if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+                safe.push(char::from(b));
+            } else {
+                safe.push_str(&format!("%{b:02X}"));
+            }
+        }
+        self.dir.join(CURSORS).join(safe)
+    }
+    async fn remove_file(&self, f: &Path) -> Result<()> {
+        let len = fs::metadata(f).await?.len();
+        fs::remove_file(f).await?;
+        decrease(&self.depth, 1);
+        decrease(&self.bytes, len);
+        Ok(())
+    }
+    /// Queued item files, oldest first.
+    async fn files(&self) -> Result<Vec<PathBuf>> {
         let mut rd = fs::read_dir(&self.dir).await?;
         let mut files: Vec<PathBuf> = Vec::new();
         while let Some(e) = rd.next_entry().await? {
@@ -45,12 +181,12 @@ impl DiskQueue {
             }
         }
         files.sort();
-        if let Some(f) = files.first() {
-            fs::remove_file(f).await?;
-        }
-        Ok(())
+        Ok(files)
     }
     async fn enforce_cap(&self) -> Result<()> {
+        if self.bytes.load(Ordering::Relaxed) <= self.cap_bytes {
+            return Ok(());
+        }
         let mut rd = fs::read_dir(&self.dir).await?;
         let mut files: Vec<(PathBuf, u64)> = Vec::new();
         let mut total = 0u64;
@@ -69,12 +205,48 @@ impl DiskQueue {
                 let _ = fs::remove_file(&old).await;
                 total = total.saturating_sub(sz);
                 files.remove(0);
+                crate::metrics::queue_evicted(1);
             } else {
                 break;
             }
         }
+        self.depth.store(files.len() as u64, Ordering::Relaxed);
+        self.bytes.store(total, Ordering::Relaxed);
         Ok(())
     }
+    /// Number of queued items and their total size in bytes, as tracked by
+    /// this handle.
+    pub fn size(&self) -> (u64, u64) {
+        (
+            self.depth.load(Ordering::Relaxed),
+            self.bytes.load(Ordering::Relaxed),
+        )
+    }
+    /// Number of queued items and their total size in bytes, counted on disk.
+    pub async fn stats(&self) -> Result<(u64, u64)> {
+        let mut rd = fs::read_dir(&self.dir).await?;
+        let (mut n, mut bytes) = (0u64, 0u64);
+        while let Some(e) = rd.next_entry().await? {
+            let md = e.metadata().await?;
+            if md.is_file() {
+                n += 1;
+                bytes += md.len();
+            }
+        }
+        Ok((n, bytes))
+    }
+}
+/// File name of a queued item; names sort in enqueue order.
+pub fn item_name(path: &Path) -> &str {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default()
+}
+/// Saturating, as another handle on the same directory may have added the item.
+fn decrease(counter: &AtomicU64, n: u64) {
+    let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+        Some(v.saturating_sub(n))
+    });
 }
 fn rand_str(n: usize) -> String {
     rand::thread_rng()
@@ -87,3 +259,57 @@ fn now_ms() -> i128 {
     use time::OffsetDateTime;
     OffsetDateTime::now_utc().unix_timestamp_nanos() as i128 / 1_000_000
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[tokio::test]
+    async fn names_follow_earlier_items_and_cursors_keep_keys_apart() {
+        let dir = std::env::temp_dir().join(format!("rea-queue-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).await.unwrap();
+        let ahead = format!("{}-aaaaaa.ndjson", now_ms() + 60_000);
+        fs::write(dir.join(&ahead), "{}\n").await.unwrap();
+        let mut q = DiskQueue::open(&dir, u64::MAX).await.unwrap();
+        assert_eq!(q.size(), (1, 3));
+        q.enqueue(b"{}\n".to_vec()).await.unwrap();
+        let files = q.files().await.unwrap();
+        assert_eq!(item_name(&files[0]), ahead);
+        assert_eq!(q.size(), (2, 6));
+        q.set_cursor("https://a.test/x_y", "1").await.unwrap();
+        q.set_cursor("https://a.test/x/y", "2").await.unwrap();
+        assert_eq!(q.cursor("https://a.test/x_y").await.unwrap().unwrap(), "1");
+        assert_eq!(q.cursor("https://a.test/x/y").await.unwrap().unwrap(), "2");
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+    #[tokio::test]
+    async fn moving_the_queue_carries_cursors_to_the_new_names() {
+        let root = std::env::temp_dir().join(format!("rea-queue-{}", uuid::Uuid::new_v4()));
+        let mut old = DiskQueue::open(&root.join("old"), u64::MAX).await.unwrap();
+        for i in 0..3 {
+            old.enqueue(format!("{i}\n").into_bytes()).await.unwrap();
+        }
+        let names: Vec<String> = old
+            .files()
+            .await
+            .unwrap()
+            .iter()
+            .map(|f| item_name(f).to_string())
+            .collect();
+        old.set_cursor("a", &names[1]).await.unwrap();
+        old.set_cursor("b", "0-before-everything").await.unwrap();
+        let mut new = DiskQueue::open(&root.join("new"), u64::MAX).await.unwrap();
+        new.set_cursor("b", "stale").await.unwrap();
+        old.move_into(&mut new, &["a", "b", "c"]).await.unwrap();
+        assert_eq!(old.size(), (0, 0));
+        assert!(old.files().await.unwrap().is_empty());
+        let after_a = new
+            .peek_batch_after(new.cursor("a").await.unwrap().as_deref(), 10, usize::MAX)
+            .await
+            .unwrap();
+        let data: Vec<Vec<u8>> = after_a.into_iter().map(|(_, d)| d).collect();
+        assert_eq!(data, [b"2\n".to_vec()]);
+        assert_eq!(new.cursor("b").await.unwrap(), None);
+        assert_eq!(new.cursor("c").await.unwrap(), None);
+        assert_eq!(new.size(), (3, 6));
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+use super::mtls::client_for;
+use crate::config::{
+    remote::{accept, RemoteDocument},
+    AgentConfig, CliOverrides,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::{StatusCode, Url};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, watch};
+use tracing::{info, warn};
+/// `{url}/{instance_id}?version=..&wait=..[&group=..]`; the server answers 200
+/// with a newer document or 304/404 once `wait` seconds pass without one.
+pub fn document_url(cfg: &AgentConfig, seen: u64) -> Result<Url> {
+    let rc = &cfg.remote_config;
+    let mut url: Url = if rc.url.is_empty() {
+        cfg.networking
+            .endpoint
+            .parse::<Url>()
+            .context("networking.endpoint")?
+            .join("/config")?
+    } else {
+        rc.url.parse().context("remote_config.url")?
+    };
+    url.path_segments_mut()
+        .map_err(|_| anyhow!("{} cannot carry a path", rc.url))?
+        .pop_if_empty()
+        .push(&cfg.common.instance_id);
+    url.query_pairs_mut()
+        .append_pair("version", &seen.to_string())
+        .append_pair("wait", &rc.wait_secs.to_string());
+    if !rc.group.is_empty() {
+        url.query_pairs_mut().append_pair("group", &rc.group);
+    }
+    Ok(url)
+}
+async fn fetch(cfg: &AgentConfig, seen: u64) -> Result<Option<RemoteDocument>> {
+    let client = client_for(cfg)?;
+    let url = document_url(cfg, seen)?;
+    let resp = client
+        .get(url.clone())
+        .timeout(Duration::from_secs(cfg.remote_config.wait_secs + 30))
+        .send()
+        .await
+        .with_context(|| format!("fetching {url}"))?;
+    match resp.status() {
+        StatusCode::NOT_MODIFIED | StatusCode::NOT_FOUND => Ok(None),
+        s if s.is_success() => Ok(Some(resp.json().await.context("parsing config document")?)),
+        s => bail!("server status {s}"),
+    }
+}
+/// Polls (or long-polls) for signed config documents while `remote_config` is
+/// enabled. Accepted documents are cached and `kick` makes the reloader apply them.
+pub fn spawn_poller(
+    mut cfg_rx: watch::Receiver<AgentConfig>,
+    path: Option<PathBuf>,
+    optional: bool,
+    overrides: CliOverrides,
+    kick: mpsc::Sender<()>,
+) {
+    tokio::spawn(async move {
+        let mut seen = 0;
+        loop {
+            let cfg = cfg_rx.borrow_and_update().clone();
+            if !cfg.remote_config.enabled {
+                if cfg_rx.changed().await.is_err() {
+                    return;
+                }
+                continue;
+            }
+            seen = seen.max(cfg.config_version.unwrap_or(0));
+            let started = Instant::now();
+            // Without long-polling, or when the server answered at once, wait before asking again.
+            let poll_again = match fetch(&cfg, seen).await {
+                Ok(Some(doc)) => {
+                    seen = seen.max(doc.version);
+                    match accept(&doc, &cfg, path.as_deref(), optional, &overrides) {
+                        Ok(_) => {
+                            info!(version = doc.version, "remote config accepted");
+                            let _ = kick.try_send(());
+                        }
+                        Err(e) => warn!(
+                            error=?e,
+                            version = doc.version,
+                            "remote config rejected; keeping current"
+                        ),
+                    }
+                    true
+                }
+                Ok(None) => started.elapsed() >= Duration::from_secs(1),
+                Err(e) => {
+                    warn!(error=?e, "remote config poll failed");
+                    false
+                }
+            };
+            if cfg.remote_config.wait_secs == 0 || !poll_again {
+                tokio::time::sleep(Duration::from_secs(cfg.remote_config.poll_secs)).await;
+            }
+        }
+    });
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn document_url_defaults_to_the_networking_server() {
+        let mut cfg = AgentConfig::default();
+        cfg.common.instance_id = "host 1".into();
+        assert_eq!(
+            document_url(&cfg, 4).unwrap().as_str(),
+            "https://127.0.0.1:8443/config/host%201?version=4&wait=30"
+        );
+        cfg.remote_config.url = "https://cfg.example/api/config/".into();
+        cfg.remote_config.group = "linux-web".into();
+        cfg.remote_config.wait_secs = 0;
+        assert_eq!(
+            document_url(&cfg, 0).unwrap().as_str(),
+            "https://cfg.example/api/config/host%201?version=0&wait=0&group=linux-web"
+        );
+    }
+}
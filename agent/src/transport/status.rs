@@ -0,0 +1,249 @@
+use crate::{
+    config::{AgentConfig, Status},
+    health, metrics,
+    shutdown::Shutdown,
+};
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{body::Incoming, header::AUTHORIZATION, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::watch,
+};
+use tracing::{info, warn};
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+/// Binds `addr` (`host:port` or `unix:/path`), replacing a stale socket file
+/// but nothing else.
+pub async fn bind(addr: &str) -> Result<Listener> {
+    if let Some(path) = addr.strip_prefix("unix:") {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            match std::fs::symlink_metadata(path) {
+                Ok(md) if md.file_type().is_socket() => std::fs::remove_file(path)
+                    .with_context(|| format!("removing stale socket {path}"))?,
+                Ok(_) => anyhow::bail!("{path} exists and is not a socket; not replacing it"),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).with_context(|| format!("checking {path}")),
+            }
+            return Ok(Listener::Unix(tokio::net::UnixListener::bind(path)?));
+        }
+        #[cfg(not(unix))]
+        anyhow::bail!("unix socket {path} is not supported on this platform");
+    }
+    Ok(Listener::Tcp(TcpListener::bind(addr).await?))
+}
+/// Binds the status listener before returning so port conflicts fail startup,
+/// then serves it in the background.
+pub async fn spawn_status_server(
+    status: &Status,
+    cfg_rx: watch::Receiver<AgentConfig>,
+    shutdown: Shutdown,
+) -> Result<()> {
+    let addr = status
+        .listen_addr()
+        .context("status server needs status.port or status.bind")?;
+    let listener = bind(&addr)
+        .await
+        .with_context(|| format!("bind status server on {addr}"))?;
+    let token = status.token.as_deref().map(Arc::from);
+    if token.is_none() && !is_local(&addr) {
+        warn!(%addr, "status server reachable beyond loopback without status.token");
+    }
+    info!(%addr, auth = token.is_some(), "status server listening");
+    tokio::spawn(accept_loop(listener, token, cfg_rx, shutdown));
+    Ok(())
+}
+fn is_local(addr: &str) -> bool {
+    addr.starts_with("unix:")
+        || addr
+            .parse::<std::net::SocketAddr>()
+            .is_ok_and(|a| a.ip().is_loopback())
+}
+pub async fn accept_loop(
+    listener: Listener,
+    token: Option<Arc<str>>,
+    cfg_rx: watch::Receiver<AgentConfig>,
+    shutdown: Shutdown,
+) {
+    const MIN_BACKOFF: Duration = Duration::from_millis(10);
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let accepted = tokio::select! {
+            _ = shutdown.cancelled() => {
+                info!("status server stopped");
+                return;
+            }
+            r = accept(&listener, token.clone(), cfg_rx.clone()) => r,
+        };
+        match accepted {
+            Ok(()) => backoff = MIN_BACKOFF,
+            Err(e) => {
+                warn!(error=?e, ?backoff, "status accept failed");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(Duration::from_secs(1));
+            }
+        }
+    }
+}
+async fn accept(
+    listener: &Listener,
+    token: Option<Arc<str>>,
+    cfg_rx: watch::Receiver<AgentConfig>,
+) -> std::io::Result<()> {
+    match listener {
+        Listener::Tcp(l) => l.accept().await.map(|(s, _)| serve(s, token, cfg_rx)),
+        #[cfg(unix)]
+        Listener::Unix(l) => l.accept().await.map(|(s, _)| serve(s, token, cfg_rx)),
+    }
+}
+fn serve<S>(stream: S, token: Option<Arc<str>>, cfg_rx: watch::Receiver<AgentConfig>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let conn = hyper::server::conn::http1::Builder::new().serve_connection(
+            TokioIo::new(stream),
+            hyper::service::service_fn(move |req| handler(req, cfg_rx.clone(), token.clone())),
+        );
+        if let Err(e) = conn.await {
+            warn!(error=?e,"status conn error");
+        }
+    });
+}
+fn reply(status: StatusCode, content_type: &str, body: impl Into<Bytes>) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", content_type)
+        .body(Full::new(body.into()))
+        .unwrap()
+}
+fn authorized(req: &Request<Incoming>, token: &str) -> bool {
+    let Some(got) = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    got.len() == token.len()
+        && got
+            .bytes()
+            .zip(token.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+async fn handler(
+    req: Request<Incoming>,
+    cfg_rx: watch::Receiver<AgentConfig>,
+    token: Option<Arc<str>>,
+) -> Result<Response<Full<Bytes>>, hyper::Error> {
+    if let Some(t) = token.as_deref() {
+        if req.uri().path() != "/healthz" && !authorized(&req, t) {
+            let mut resp = reply(StatusCode::UNAUTHORIZED, "text/plain", "unauthorized");
+            resp.headers_mut()
+                .insert("WWW-Authenticate", "Bearer".parse().unwrap());
+            return Ok(resp);
+        }
+    }
+    let cfg = cfg_rx.borrow().clone();
+    let json = "application/json";
+    Ok(match (req.method(), req.uri().path()) {
+        (&Method::GET, "/healthz") => reply(StatusCode::OK, "text/plain", "ok"),
+        (&Method::GET, "/readyz") => match health::readiness(&cfg) {
+            p if p.is_empty() => reply(StatusCode::OK, "text/plain", "ready"),
+            p => reply(StatusCode::SERVICE_UNAVAILABLE, "text/plain", p.join("\n")),
+        },
+        (&Method::GET, "/status") => reply(StatusCode::OK, json, health::status(&cfg).to_string()),
+        (&Method::GET, "/events/recent") => {
+            let limit = req
+                .uri()
+                .query()
+                .and_then(|q| q.split('&').find_map(|kv| kv.strip_prefix("limit=")))
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(50);
+            reply(StatusCode::OK, json, health::recent(limit))
+        }
+        (&Method::GET, "/metrics") => reply(
+            StatusCode::OK,
+            "text/plain; version=0.0.4",
+            metrics::render(),
+        ),
+        _ => reply(StatusCode::NOT_FOUND, "text/plain", ""),
+    })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    async fn get(addr: std::net::SocketAddr, path: &str, auth: Option<&str>) -> String {
+        let mut s = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let auth = auth
+            .map(|t| format!("Authorization: Bearer {t}\r\n"))
+            .unwrap_or_default();
+        let req = format!("GET {path} HTTP/1.1\r\nHost: x\r\n{auth}Connection: close\r\n\r\n");
+        s.write_all(req.as_bytes()).await.unwrap();
+        let mut out = String::new();
+        s.read_to_string(&mut out).await.unwrap();
+        out
+    }
+    #[tokio::test]
+    async fn port_conflict_is_reported_to_the_caller() {
+        let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let status = Status {
+            bind: Some(first.local_addr().unwrap().to_string()),
+            ..Default::default()
+        };
+        let (_tx, rx) = watch::channel(AgentConfig::default());
+        let err = spawn_status_server(&status, rx, Shutdown::default())
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("bind status server"));
+    }
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_bind_replaces_only_a_stale_socket() {
+        let path = std::env::temp_dir().join(format!("rea-status-{}.sock", uuid::Uuid::new_v4()));
+        let addr = format!("unix:{}", path.display());
+        drop(bind(&addr).await.unwrap());
+        assert!(bind(&addr).await.is_ok());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::write(&path, "keep me").unwrap();
+        let err = bind(&addr).await.map(|_| ()).unwrap_err();
+        assert!(err.to_string().contains("not a socket"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep me");
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[tokio::test]
+    async fn bearer_token_guards_everything_but_healthz() {
+        let l = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = l.local_addr().unwrap();
+        let (_tx, rx) = watch::channel(AgentConfig::default());
+        let shutdown = Shutdown::default();
+        tokio::spawn(accept_loop(
+            Listener::Tcp(l),
+            Some(Arc::from("s3cret")),
+            rx,
+            shutdown.clone(),
+        ));
+        assert!(get(addr, "/healthz", None)
+            .await
+            .starts_with("HTTP/1.1 200"));
+        assert!(get(addr, "/status", None).await.starts_with("HTTP/1.1 401"));
+        assert!(get(addr, "/status", Some("wrong!"))
+            .await
+            .starts_with("HTTP/1.1 401"));
+        assert!(get(addr, "/status", Some("s3cret"))
+            .await
+            .starts_with("HTTP/1.1 200"));
+    }
+}
@@ -0,0 +1,272 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+pub const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// What `GET /updates` returns: the latest release and one signed binary per platform.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: String,
+    pub artifacts: Vec<Artifact>,
+}
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Artifact {
+    /// `{os}-{arch}` as in `std::env::consts`, e.g. `linux-x86_64`.
+    pub target: String,
+    /// Absolute, or relative to the manifest URL.
+    pub url: String,
+    /// Base64 Ed25519 signature over the binary.
+    pub signature: String,
+}
+impl Manifest {
+    pub fn artifact(&self, target: &str) -> Option<&Artifact> {
+        self.artifacts.iter().find(|a| a.target == target)
+    }
+}
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    UpToDate { version: String },
+    Available { version: String },
+    Updated { from: String, to: String },
+}
+/// How the swapped-in binary proves it starts: run with `args`, it must print
+/// its version on stdout and exit successfully within `timeout`.
+pub struct HealthCheck {
+    pub args: Vec<OsString>,
+    pub timeout: Duration,
+}
+pub fn target() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+fn version_parts(v: &str) -> Vec<u64> {
+    v.trim_start_matches('v')
+        .split(['.', '-', '+'])
+        .map_while(|p| p.parse().ok())
+        .collect()
+}
+/// Numeric comparison of dotted versions; pre-release and build suffixes are ignored.
+pub fn is_newer(candidate: &str, current: &str) -> bool {
+    version_parts(candidate) > version_parts(current)
+}
+pub fn parse_public_key(b64: &str) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = STANDARD
+        .decode(b64.trim())
+        .context("decoding public key")?
+        .try_into()
+        .map_err(|_| anyhow!("public key must be 32 bytes"))?;
+    VerifyingKey::from_bytes(&bytes).context("invalid Ed25519 public key")
+}
+pub fn verify_signature(key: &VerifyingKey, data: &[u8], signature_b64: &str) -> Result<()> {
+    let sig = STANDARD
+        .decode(signature_b64.trim())
+        .context("decoding signature")?;
+    let sig = Signature::from_slice(&sig).context("malformed signature")?;
+    key.verify_strict(data, &sig)
+        .context("signature does not match the pinned update key")
+}
+fn sibling(exe: &Path, suffix: &str) -> PathBuf {
+    let mut p = exe.as_os_str().to_os_string();
+    p.push(suffix);
+    p.into()
+}
+fn write_executable(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut f = fs::File::create(path).with_context(|| format!("create {}", path.display()))?;
+    f.write_all(bytes)?;
+    f.sync_all()?;
+    drop(f);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+/// Unix replaces the running binary with one atomic rename; Windows cannot
+/// replace a running image, so it is renamed aside first.
+fn swap_in(exe: &Path, staged: &Path, old: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        fs::hard_link(exe, old)?;
+        fs::rename(staged, exe)
+    }
+    #[cfg(not(unix))]
+    {
+        fs::rename(exe, old)?;
+        fs::rename(staged, exe).map_err(|e| {
+            let _ = fs::rename(old, exe);
+            e
+        })
+    }
+}
+fn restore(exe: &Path, old: &Path) -> io::Result<()> {
+    #[cfg(not(unix))]
+    fs::remove_file(exe)?;
+    fs::rename(old, exe)
+}
+async fn health_check(exe: &Path, version: &str, hc: &HealthCheck) -> Result<()> {
+    let run = tokio::process::Command::new(exe)
+        .args(&hc.args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let out = tokio::time::timeout(hc.timeout, run)
+        .await
+        .map_err(|_| anyhow!("no answer within {:?}", hc.timeout))?
+        .context("starting the new binary")?;
+    if !out.status.success() {
+        bail!("new binary exited with {}", out.status);
+    }
+    let reported = String::from_utf8_lossy(&out.stdout);
+    if reported.trim() != version {
+        bail!(
+            "new binary reports {:?}, expected {version}",
+            reported.trim()
+        );
+    }
+    Ok(())
+}
+/// Stages `binary` next to `exe`, swaps it in and health-checks it; on failure the
+/// previous binary is put back. The replaced binary is kept as `<exe>.old`.
+pub async fn install(exe: &Path, binary: &[u8], version: &str, hc: &HealthCheck) -> Result<()> {
+    let staged = sibling(exe, ".new");
+    let old = sibling(exe, ".old");
+    let _ = fs::remove_file(&old);
+    write_executable(&staged, binary)?;
+    swap_in(exe, &staged, &old).with_context(|| format!("replacing {}", exe.display()))?;
+    if let Err(e) = health_check(exe, version, hc).await {
+        restore(exe, &old).context("rolling back to the previous binary")?;
+        return Err(e.context(format!("{version} failed its health check; rolled back")));
+    }
+    Ok(())
+}
+#[cfg(feature = "networking")]
+pub fn manifest_url(cfg: &crate::config::AgentConfig) -> Result<reqwest::Url> {
+    if !cfg.update.manifest_url.is_empty() {
+        return cfg
+            .update
+            .manifest_url
+            .parse()
+            .context("update.manifest_url");
+    }
+    let endpoint: reqwest::Url = cfg
+        .networking
+        .endpoint
+        .parse()
+        .context("networking.endpoint")?;
+    Ok(endpoint.join("/updates")?)
+}
+/// Fetches the manifest and, unless `check_only`, downloads, verifies and installs
+/// a newer release over `exe`.
+#[cfg(feature = "networking")]
+pub async fn run(
+    cfg: &crate::config::AgentConfig,
+    exe: &Path,
+    hc: &HealthCheck,
+    check_only: bool,
+) -> Result<Outcome> {
+    if cfg.update.public_key.is_empty() {
+        bail!("update.public_key is not set; refusing to install unsigned binaries");
+    }
+    let key = parse_public_key(&cfg.update.public_key)?;
+    let client = crate::transport::mtls::client_for(cfg)?;
+    let url = manifest_url(cfg)?;
+    let manifest: Manifest = client
+        .get(url.clone())
+        .send()
+        .await
+        .with_context(|| format!("fetching {url}"))?
+        .error_for_status()?
+        .json()
+        .await
+        .context("parsing update manifest")?;
+    if !is_newer(&manifest.version, CURRENT_VERSION) {
+        return Ok(Outcome::UpToDate {
+            version: CURRENT_VERSION.into(),
+        });
+    }
+    if check_only {
+        return Ok(Outcome::Available {
+            version: manifest.version,
+        });
+    }
+    let target = target();
+    let artifact = manifest
+        .artifact(&target)
+        .with_context(|| format!("release {} has no build for {target}", manifest.version))?;
+    let binary_url = url.join(&artifact.url)?;
+    let binary = client
+        .get(binary_url.clone())
+        .send()
+        .await
+        .with_context(|| format!("downloading {binary_url}"))?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    verify_signature(&key, &binary, &artifact.signature)
+        .with_context(|| format!("verifying {binary_url}"))?;
+    install(exe, &binary, &manifest.version, hc).await?;
+    Ok(Outcome::Updated {
+        from: CURRENT_VERSION.into(),
+        to: manifest.version,
+    })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    #[test]
+    fn versions_compare_numerically_and_signatures_are_pinned() {
+        assert!(is_newer("0.10.0", "0.9.3"));
+        assert!(is_newer("v1.2.0", "1.1.9-rc1"));
+        assert!(!is_newer("0.1.0", "0.1.0"));
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let public = STANDARD.encode(key.verifying_key().as_bytes());
+        let sig = STANDARD.encode(key.sign(b"binary").to_bytes());
+        let pinned = parse_public_key(&public).unwrap();
+        verify_signature(&pinned, b"binary", &sig).unwrap();
+        assert!(verify_signature(&pinned, b"tampered", &sig).is_err());
+        let other = SigningKey::from_bytes(&[8; 32]);
+        let pinned = parse_public_key(&STANDARD.encode(other.verifying_key().as_bytes())).unwrap();
+        assert!(verify_signature(&pinned, b"binary", &sig).is_err());
+        assert!(parse_public_key("c2hvcnQ=").is_err());
+    }
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn swaps_healthy_binary_and_rolls_back_broken_one() {
+        let dir = std::env::temp_dir().join(format!("rea-update-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let exe = dir.join("agent");
+        write_executable(&exe, b"#!/bin/sh\necho 0.1.0\n").unwrap();
+        let hc = HealthCheck {
+            args: vec!["update".into(), "--self-test".into()],
+            timeout: Duration::from_secs(10),
+        };
+        let good = b"#!/bin/sh\n[ \"$2\" = --self-test ] && echo 0.2.0\n";
+        install(&exe, good, "0.2.0", &hc).await.unwrap();
+        assert_eq!(fs::read(&exe).unwrap(), good);
+        assert_eq!(
+            fs::read(dir.join("agent.old")).unwrap(),
+            b"#!/bin/sh\necho 0.1.0\n"
+        );
+        let err = install(&exe, b"#!/bin/sh\nexit 3\n", "0.3.0", &hc)
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("rolled back"), "{err:#}");
+        assert_eq!(fs::read(&exe).unwrap(), good);
+        let err = install(&exe, b"#!/bin/sh\necho 0.2.0\n", "0.3.0", &hc)
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("expected 0.3.0"), "{err:#}");
+        assert_eq!(fs::read(&exe).unwrap(), good);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
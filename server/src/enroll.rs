@@ -0,0 +1,179 @@
+use anyhow::{bail, Context, Result};
+use rcgen::{
+    Certificate, CertificateParams, CertificateSigningRequestParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SerialNumber,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use time::{Duration, OffsetDateTime};
+/// Lifetime of issued client certificates; agents renew well before it ends.
+const VALIDITY: Duration = Duration::days(90);
+#[derive(Deserialize)]
+pub struct EnrollRequest {
+    pub instance_id: String,
+    #[serde(default)]
+    pub token: String,
+    pub csr: String,
+}
+#[derive(Serialize)]
+pub struct Issued {
+    pub certificate: String,
+    pub ca: String,
+}
+/// Signs agent CSRs with the CA that also verifies client certificates.
+pub struct Issuer {
+    ca_pem: String,
+    ca: Certificate,
+    key: KeyPair,
+    /// One unused enrollment token per line; each is removed when redeemed.
+    tokens: PathBuf,
+    lock: Mutex<()>,
+}
+impl Issuer {
+    /// Loads `ca.crt` and its key from `ca.key` next to it.
+    pub fn load(ca_path: &Path, tokens: PathBuf) -> Result<Self> {
+        let ca_pem =
+            fs::read_to_string(ca_path).with_context(|| format!("read {}", ca_path.display()))?;
+        let key_path = ca_path.with_extension("key");
+        let key = KeyPair::from_pem(
+            &fs::read_to_string(&key_path)
+                .with_context(|| format!("read {}", key_path.display()))?,
+        )
+        .context("CA key")?;
+        let ca = CertificateParams::from_ca_cert_pem(&ca_pem)
+            .context("CA certificate")?
+            .self_signed(&key)?;
+        Ok(Self {
+            ca_pem,
+            ca,
+            key,
+            tokens,
+            lock: Mutex::new(()),
+        })
+    }
+    /// Removes `token` from the token file; `false` if it was not there.
+    fn redeem(&self, token: &str) -> Result<bool> {
+        let _guard = self.lock.lock().unwrap();
+        let contents = match fs::read_to_string(&self.tokens) {
+            Ok(c) => c,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e).context("reading enrollment tokens"),
+        };
+        let mut found = false;
+        let rest: String = contents
+            .lines()
+            .filter(|l| {
+                let hit = !found && l.trim() == token;
+                found |= hit;
+                !hit
+            })
+            .map(|l| format!("{l}\n"))
+            .collect();
+        if found {
+            fs::write(&self.tokens, rest).context("updating enrollment tokens")?;
+        }
+        Ok(found)
+    }
+    /// A first enrollment must redeem a token; a renewal must come over a
+    /// connection whose verified client certificate names the same instance.
+    pub fn authorize(&self, req: &EnrollRequest, peer: Option<&str>) -> Result<()> {
+        if !crate::plain(&req.instance_id) {
+            bail!("invalid instance_id");
+        }
+        if peer == Some(req.instance_id.as_str()) {
+            return Ok(());
+        }
+        if req.token.is_empty() || !self.redeem(req.token.trim())? {
+            bail!("unknown or already used enrollment token");
+        }
+        Ok(())
+    }
+    /// Signs the CSR's public key as a client certificate for `instance_id`;
+    /// everything else the CSR asks for is ignored.
+    pub fn issue(&self, req: &EnrollRequest) -> Result<Issued> {
+        let mut csr = CertificateSigningRequestParams::from_pem(&req.csr).context("parsing CSR")?;
+        let mut params = CertificateParams::default();
+        params.distinguished_name = DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, req.instance_id.as_str());
+        params.is_ca = IsCa::NoCa;
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        params.serial_number = Some(SerialNumber::from_slice(&rand::random::<[u8; 16]>()));
+        let now = OffsetDateTime::now_utc();
+        params.not_before = now - Duration::minutes(5);
+        params.not_after = now + VALIDITY;
+        csr.params = params;
+        let cert = csr.signed_by(&self.ca, &self.key)?;
+        Ok(Issued {
+            certificate: cert.pem(),
+            ca: self.ca_pem.clone(),
+        })
+    }
+}
+/// Common name of a DER certificate, used to identify the agent on a connection.
+pub fn common_name(der: &[u8]) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    let cn = cert.subject().iter_common_name().next()?;
+    cn.as_str().ok().map(String::from)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn csr(key: &KeyPair) -> String {
+        CertificateParams::new(vec![])
+            .unwrap()
+            .serialize_request(key)
+            .unwrap()
+            .pem()
+            .unwrap()
+    }
+    #[test]
+    fn redeems_a_token_once_and_renews_only_for_the_same_instance() {
+        let dir = std::env::temp_dir().join(format!("rea-enroll-{}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::default();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "rea-test-ca");
+        params.is_ca = IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign];
+        let ca = params.self_signed(&ca_key).unwrap();
+        fs::write(dir.join("ca.crt"), ca.pem()).unwrap();
+        fs::write(dir.join("ca.key"), ca_key.serialize_pem()).unwrap();
+        let tokens = dir.join("enroll-tokens");
+        fs::write(&tokens, "tok-1\ntok-2\n").unwrap();
+        let issuer = Issuer::load(&dir.join("ca.crt"), tokens.clone()).unwrap();
+        let key = KeyPair::generate().unwrap();
+        let mut req = EnrollRequest {
+            instance_id: "agent-1".into(),
+            token: "tok-1".into(),
+            csr: csr(&key),
+        };
+        issuer.authorize(&req, None).unwrap();
+        assert_eq!(fs::read_to_string(&tokens).unwrap(), "tok-2\n");
+        assert!(issuer.authorize(&req, None).is_err());
+        let issued = issuer.issue(&req).unwrap();
+        let (_, pem) = x509_parser::pem::parse_x509_pem(issued.certificate.as_bytes()).unwrap();
+        assert_eq!(common_name(&pem.contents).as_deref(), Some("agent-1"));
+        let cert = pem.parse_x509().unwrap();
+        assert_eq!(cert.issuer().to_string(), "CN=rea-test-ca");
+        assert_eq!(cert.public_key().raw, key.public_key_der());
+        assert_eq!(issued.ca, ca.pem());
+        req.token.clear();
+        req.csr = csr(&KeyPair::generate().unwrap());
+        issuer.authorize(&req, Some("agent-1")).unwrap();
+        assert!(issuer.authorize(&req, Some("agent-2")).is_err());
+        assert!(issuer.authorize(&req, None).is_err());
+        let renewed = issuer.issue(&req).unwrap();
+        assert_ne!(renewed.certificate, issued.certificate);
+        assert_eq!(fs::read_to_string(&tokens).unwrap(), "tok-2\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,21 @@
+use anyhow::Result;
+use std::path::Path;
+use tls_keys::{read_certs, read_pkcs12, read_private_key, Chain};
+/// Password of an encrypted key or a PKCS#12 bundle.
+const PASSWORD_VAR: &str = "REA_SERVER_KEY_PASSWORD";
+/// The server identity: a `.p12`/`.pfx` bundle given as the certificate (the key
+/// argument is then ignored), or a PEM chain plus a PKCS#8, PKCS#1, SEC1 or
+/// encrypted PKCS#8 key.
+pub fn load_identity(cert: &Path, key: &Path) -> Result<Chain> {
+    let password = std::env::var(PASSWORD_VAR).ok();
+    if cert
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("p12") || e.eq_ignore_ascii_case("pfx"))
+    {
+        return read_pkcs12(cert, password.as_deref(), PASSWORD_VAR);
+    }
+    Ok((
+        read_certs(cert)?,
+        read_private_key(key, password.as_deref(), PASSWORD_VAR)?,
+    ))
+}
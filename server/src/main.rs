@@ -1,12 +1,20 @@
+mod enroll;
+mod keys;
 use anyhow::{Context, Result};
-use hyper::{Body, Method, Request, Response};
+use bytes::Bytes;
+use enroll::{EnrollRequest, Issuer};
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Incoming, Method, Request, Response, StatusCode};
 use hyper_util::rt::TokioIo;
-use rustls::{
-    pki_types::{CertificateDer, PrivateKeyDer},
-    ServerConfig,
+use rustls::{server::WebPkiClientVerifier, RootCertStore, ServerConfig};
+use rustls_pemfile::certs;
+use std::{
+    fs::File,
+    io::BufReader,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
-use rustls_pemfile::{certs, pkcs8_private_keys};
-use std::{fs::File, io::BufReader, io::Write, path::PathBuf};
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 use tokio::net::TcpListener;
 use tokio_rustls::TlsAcceptor;
@@ -20,18 +28,38 @@ async fn main() -> Result<()> {
     let mut args = std::env::args().skip(1);
     let cert: PathBuf = args
         .next()
-        .unwrap_or_else(|| "configs/certs/server.crt".into());
+        .unwrap_or_else(|| "configs/certs/server.crt".into())
+        .into();
     let key: PathBuf = args
         .next()
-        .unwrap_or_else(|| "configs/certs/server.key".into());
+        .unwrap_or_else(|| "configs/certs/server.key".into())
+        .into();
+    // With a CA, client certificates signed by it are verified and `/ingest`
+    // requires one; `/enroll` issues them (the CA key is read from `ca.key`).
+    let ca: Option<PathBuf> = args.next().map(Into::into);
+    // Served content: `updates/` holds manifest.json and the signed binaries;
+    // `config/` holds signed config documents (see `serve_config`).
+    let dir: Arc<PathBuf> = Arc::new(
+        std::env::var_os("REA_SERVER_DIR")
+            .unwrap_or_else(|| "configs/server".into())
+            .into(),
+    );
     let addr = "127.0.0.1:8443";
-    let cfg = tls_config(&cert, &key).context("tls config")?;
-    let acceptor = TlsAcceptor::from(std::sync::Arc::new(cfg));
+    let issuer: Option<Arc<Issuer>> = match &ca {
+        Some(ca) => Some(Arc::new(
+            Issuer::load(ca, dir.join("enroll-tokens")).context("enrollment CA")?,
+        )),
+        None => None,
+    };
+    let cfg = tls_config(&cert, &key, ca.as_deref()).context("tls config")?;
+    let acceptor = TlsAcceptor::from(Arc::new(cfg));
     let listener = TcpListener::bind(addr).await?;
-    info!(%addr,"HTTPS receiver listening");
+    info!(%addr, dir = %dir.display(), client_auth = ca.is_some(), "HTTPS receiver listening");
     loop {
         let (tcp, _) = listener.accept().await?;
         let acceptor = acceptor.clone();
+        let dir = dir.clone();
+        let issuer = issuer.clone();
         tokio::spawn(async move {
             let tls = match acceptor.accept(tcp).await {
                 Ok(s) => s,
@@ -40,9 +68,18 @@ async fn main() -> Result<()> {
                     return;
                 }
             };
+            let peer: Option<Arc<str>> = tls
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| enroll::common_name(certs.first()?))
+                .map(Into::into);
             let io = TokioIo::new(tls);
+            let svc = hyper::service::service_fn(move |req| {
+                handler(req, dir.clone(), issuer.clone(), peer.clone())
+            });
             if let Err(e) = hyper::server::conn::http1::Builder::new()
-                .serve_connection(io, hyper::service::service_fn(handler))
+                .serve_connection(io, svc)
                 .await
             {
                 tracing::warn!(error=?e,"connection failed");
@@ -50,34 +87,210 @@ async fn main() -> Result<()> {
         });
     }
 }
-async fn handler(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
-    match (req.method(), req.uri().path()) {
+fn reply(status: StatusCode, body: impl Into<Bytes>) -> Response<Full<Bytes>> {
+    let mut resp = Response::new(Full::new(body.into()));
+    *resp.status_mut() = status;
+    resp
+}
+fn plain(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\'])
+}
+fn with_type(mut resp: Response<Full<Bytes>>, content_type: &str) -> Response<Full<Bytes>> {
+    resp.headers_mut()
+        .insert(http::header::CONTENT_TYPE, content_type.parse().unwrap());
+    resp
+}
+/// Largest file `serve_file` reads into memory.
+const MAX_SERVED_BYTES: u64 = 512 * 1024 * 1024;
+/// Serves `dir/name` when `name` is a plain file name naming a regular file of
+/// at most `MAX_SERVED_BYTES`; nothing else is opened.
+fn serve_file(dir: &Path, name: &str, content_type: &str) -> Response<Full<Bytes>> {
+    if !plain(name) {
+        return reply(StatusCode::NOT_FOUND, "not found");
+    }
+    let path = dir.join(name);
+    match std::fs::metadata(&path) {
+        Ok(m) if m.is_file() && m.len() <= MAX_SERVED_BYTES => {}
+        Ok(m) if m.is_file() => return reply(StatusCode::PAYLOAD_TOO_LARGE, "file too large"),
+        _ => return reply(StatusCode::NOT_FOUND, "not found"),
+    }
+    match std::fs::read(&path) {
+        Ok(body) => with_type(reply(StatusCode::OK, body), content_type),
+        Err(_) => reply(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+/// The most specific document for an agent: `instances/<id>.json`, then
+/// `groups/<group>.json`, then `default.json`.
+fn config_document(dir: &Path, id: &str, group: Option<&str>) -> Option<(u64, Vec<u8>)> {
+    let mut candidates = vec![dir.join("instances").join(format!("{id}.json"))];
+    if let Some(g) = group.filter(|g| plain(g)) {
+        candidates.push(dir.join("groups").join(format!("{g}.json")));
+    }
+    candidates.push(dir.join("default.json"));
+    let body = candidates.iter().find_map(|p| std::fs::read(p).ok())?;
+    let doc: serde_json::Value = serde_json::from_slice(&body).ok()?;
+    Some((doc["version"].as_u64()?, body))
+}
+/// `GET /config/<id>?version=N&wait=S&group=G`: a document newer than `N`, waiting
+/// up to `S` (at most 60) seconds for one to appear; 304 if none did.
+async fn serve_config(dir: &Path, id: &str, query: &str) -> Response<Full<Bytes>> {
+    if !plain(id) {
+        return reply(StatusCode::NOT_FOUND, "not found");
+    }
+    let param = |name: &str| {
+        query
+            .split('&')
+            .filter_map(|kv| kv.split_once('='))
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| v)
+    };
+    let seen: u64 = param("version").and_then(|v| v.parse().ok()).unwrap_or(0);
+    let wait: u64 = param("wait").and_then(|v| v.parse().ok()).unwrap_or(0);
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(wait.min(60));
+    loop {
+        match config_document(dir, id, param("group")) {
+            Some((version, body)) if version > seen => {
+                return with_type(reply(StatusCode::OK, body), "application/json")
+            }
+            None if wait == 0 => return reply(StatusCode::NOT_FOUND, "not found"),
+            _ if tokio::time::Instant::now() >= deadline => {
+                return reply(StatusCode::NOT_MODIFIED, "")
+            }
+            _ => tokio::time::sleep(std::time::Duration::from_secs(1)).await,
+        }
+    }
+}
+/// `POST /enroll`: signs the agent's CSR when it redeems a token or renews over
+/// a connection authenticated as the same instance.
+async fn serve_enroll(
+    issuer: Option<&Issuer>,
+    body: &[u8],
+    peer: Option<&str>,
+) -> Response<Full<Bytes>> {
+    let Some(issuer) = issuer else {
+        return reply(StatusCode::NOT_FOUND, "enrollment needs a CA");
+    };
+    let req: EnrollRequest = match serde_json::from_slice(body) {
+        Ok(r) => r,
+        Err(e) => return reply(StatusCode::BAD_REQUEST, e.to_string()),
+    };
+    if let Err(e) = issuer.authorize(&req, peer) {
+        tracing::warn!(instance = %req.instance_id, error = %e, "enrollment refused");
+        return reply(StatusCode::FORBIDDEN, e.to_string());
+    }
+    match issuer.issue(&req) {
+        Ok(issued) => {
+            info!(
+                instance = %req.instance_id,
+                renewal = peer.is_some(),
+                "client certificate issued"
+            );
+            with_type(
+                reply(StatusCode::OK, serde_json::to_vec(&issued).unwrap()),
+                "application/json",
+            )
+        }
+        Err(e) => reply(StatusCode::BAD_REQUEST, format!("{e:#}")),
+    }
+}
+async fn handler(
+    req: Request<Incoming>,
+    dir: Arc<PathBuf>,
+    issuer: Option<Arc<Issuer>>,
+    peer: Option<Arc<str>>,
+) -> Result<Response<Full<Bytes>>, hyper::Error> {
+    let path = req.uri().path().to_string();
+    let query = req.uri().query().unwrap_or_default().to_string();
+    match (req.method(), path.as_str()) {
+        (&Method::POST, "/ingest") if issuer.is_some() && peer.is_none() => Ok(reply(
+            StatusCode::UNAUTHORIZED,
+            "client certificate required",
+        )),
         (&Method::POST, "/ingest") => {
-            let mut body = hyper::body::to_bytes(req.into_body()).await?;
+            let mut body = req.into_body().collect().await?.to_bytes();
             if body.starts_with(&[40, 181, 47, 253]) {
                 if let Ok(decompressed) = zstd::stream::decode_all(&body[..]) {
-                    body = bytes::Bytes::from(decompressed);
+                    body = Bytes::from(decompressed);
                 }
             }
             let now = OffsetDateTime::now_utc().format(&Rfc3339).unwrap();
             print!("{} ", now);
             std::io::stdout().write_all(&body).ok();
-            Ok(Response::new(Body::from("ok")))
+            Ok(reply(StatusCode::OK, "ok"))
+        }
+        (&Method::POST, "/enroll") => {
+            let body = req.into_body().collect().await?.to_bytes();
+            Ok(serve_enroll(issuer.as_deref(), &body, peer.as_deref()).await)
         }
-        _ => Ok(Response::builder().status(404).body(Body::empty()).unwrap()),
+        (&Method::GET, "/updates") => Ok(serve_file(
+            &dir.join("updates"),
+            "manifest.json",
+            "application/json",
+        )),
+        (&Method::GET, p) if p.starts_with("/updates/") => Ok(serve_file(
+            &dir.join("updates"),
+            &p["/updates/".len()..],
+            "application/octet-stream",
+        )),
+        // Anonymous clients may connect to enroll, so with a CA a config
+        // document is only served to the instance it belongs to.
+        (&Method::GET, p)
+            if p.starts_with("/config/")
+                && issuer.is_some()
+                && peer.as_deref() != Some(&p["/config/".len()..]) =>
+        {
+            Ok(reply(
+                StatusCode::FORBIDDEN,
+                "client certificate does not match the instance",
+            ))
+        }
+        (&Method::GET, p) if p.starts_with("/config/") => {
+            Ok(serve_config(&dir.join("config"), &p["/config/".len()..], &query).await)
+        }
+        _ => Ok(reply(StatusCode::NOT_FOUND, "")),
     }
 }
-fn tls_config(cert_path: &PathBuf, key_path: &PathBuf) -> Result<ServerConfig> {
-    let cert_file = &mut BufReader::new(File::open(cert_path)?);
-    let key_file = &mut BufReader::new(File::open(key_path)?);
-    let certs: Vec<CertificateDer<'static>> = certs(cert_file)?.into_iter().collect();
-    let mut keys: Vec<PrivateKeyDer> = pkcs8_private_keys(key_file)?
-        .into_iter()
-        .map(Into::into)
-        .collect();
-    let key = keys.remove(0);
-    let cfg = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)?;
+fn tls_config(cert_path: &Path, key_path: &Path, ca_path: Option<&Path>) -> Result<ServerConfig> {
+    let (chain, key) = keys::load_identity(cert_path, key_path)?;
+    let builder = rustls::ServerConfig::builder();
+    let builder = match ca_path {
+        // Anonymous clients may still connect, to enroll and fetch updates.
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for c in certs(&mut BufReader::new(File::open(ca)?)) {
+                roots.add(c?)?;
+            }
+            builder.with_client_cert_verifier(
+                WebPkiClientVerifier::builder(Arc::new(roots))
+                    .allow_unauthenticated()
+                    .build()?,
+            )
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let cfg = builder.with_single_cert(chain, key)?;
     Ok(cfg)
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn serve_file_only_opens_plain_regular_files_in_its_directory() {
+        let dir = std::env::temp_dir().join(format!("rea-serve-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("manifest.json"), "{}").unwrap();
+        let status = |name: &str| serve_file(&dir, name, "application/json").status();
+        assert_eq!(status("manifest.json"), StatusCode::OK);
+        for name in [
+            "/dev/zero",
+            "../manifest.json",
+            "sub",
+            ".hidden",
+            "",
+            "missing",
+        ] {
+            assert_eq!(status(name), StatusCode::NOT_FOUND, "{name}");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,20 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
 use clap::{Parser, Subcommand};
-use std::{fs, path::PathBuf};
+use ed25519_dalek::{Signer, SigningKey};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair, KeyUsagePurpose,
+};
+use serde_json::{json, Value};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
 #[derive(Parser, Debug)]
 #[command(name = "xtask")]
@@ -16,8 +30,58 @@ enum Commands {
         #[arg(long, default_value = "127.0.0.1")]
         dns: String,
     },
+    /// Generate a local CA plus server and client certs signed by it (mTLS / enrollment)
+    Mtls {
+        #[arg(long, default_value = "127.0.0.1")]
+        dns: String,
+        /// Common name of the client certificate
+        #[arg(long, default_value = "rea-dev")]
+        client: String,
+    },
+    /// Add a one-time agent enrollment token to the server's token file
+    EnrollToken {
+        #[arg(long, default_value = "configs/server/enroll-tokens")]
+        file: PathBuf,
+    },
     /// Run fmt+clippy
     Lint,
+    /// Generate an Ed25519 key pair for signing releases (or, with --out, config documents)
+    UpdateKey {
+        #[arg(long, default_value = "configs/certs/update.key")]
+        out: PathBuf,
+    },
+    /// Sign an agent binary and publish it in the server's update manifest
+    SignUpdate {
+        #[arg(long, default_value = "configs/certs/update.key")]
+        key: PathBuf,
+        /// Must match the version the binary reports (its Cargo package version)
+        #[arg(long)]
+        version: String,
+        #[arg(long)]
+        binary: PathBuf,
+        /// `{os}-{arch}` of the binary, e.g. linux-x86_64 or windows-x86_64
+        #[arg(long)]
+        target: String,
+        #[arg(long, default_value = "configs/server/updates")]
+        dir: PathBuf,
+    },
+    /// Sign an agent config file for remote delivery by the server
+    SignConfig {
+        #[arg(long, default_value = "configs/certs/config.key")]
+        key: PathBuf,
+        /// Must increase with every document an agent is meant to accept
+        #[arg(long)]
+        version: u64,
+        #[arg(long)]
+        config: PathBuf,
+        /// Serve to this instance only; with neither flag, to every agent
+        #[arg(long, conflicts_with = "group")]
+        instance: Option<String>,
+        #[arg(long)]
+        group: Option<String>,
+        #[arg(long, default_value = "configs/server/config")]
+        dir: PathBuf,
+    },
 }
 
 fn main() -> Result<()> {
@@ -28,20 +92,176 @@ fn main() -> Result<()> {
             fs::create_dir_all(&dir)?;
             // Simple & stable API
             let cert = rcgen::generate_simple_self_signed(vec![dns])?;
-            let crt = cert.serialize_pem()?;
-            let key = cert.serialize_private_key_pem();
-            fs::write(dir.join("server.crt"), crt)?;
-            fs::write(dir.join("server.key"), key)?;
+            fs::write(dir.join("server.crt"), cert.cert.pem())?;
+            fs::write(dir.join("server.key"), cert.key_pair.serialize_pem())?;
             println!("Wrote configs/certs/server.crt and server.key");
         }
+        Commands::Mtls { dns, client } => {
+            let dir = PathBuf::from("configs/certs");
+            fs::create_dir_all(&dir)?;
+            let ca_key = KeyPair::generate()?;
+            let mut params = CertificateParams::new(Vec::<String>::new())?;
+            params
+                .distinguished_name
+                .push(DnType::CommonName, "REA local CA");
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params.key_usages = vec![
+                KeyUsagePurpose::KeyCertSign,
+                KeyUsagePurpose::CrlSign,
+                KeyUsagePurpose::DigitalSignature,
+            ];
+            let ca = params.self_signed(&ca_key)?;
+            fs::write(dir.join("ca.crt"), ca.pem())?;
+            fs::write(dir.join("ca.key"), ca_key.serialize_pem())?;
+            leaf(
+                &dir,
+                "server",
+                &dns,
+                ExtendedKeyUsagePurpose::ServerAuth,
+                &ca,
+                &ca_key,
+            )?;
+            leaf(
+                &dir,
+                "client",
+                &client,
+                ExtendedKeyUsagePurpose::ClientAuth,
+                &ca,
+                &ca_key,
+            )?;
+            println!(
+                "Wrote ca, server and client certs and keys to {}",
+                dir.display()
+            );
+        }
+        Commands::EnrollToken { file } => {
+            if let Some(dir) = file.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let token = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 24]>());
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&file)
+                .with_context(|| format!("open {}", file.display()))?
+                .write_all(format!("{token}\n").as_bytes())?;
+            println!("Added a token to {}", file.display());
+            println!("[enrollment]\nenabled = true\ntoken = \"{token}\"");
+        }
         Commands::Lint => {
             run("cargo", &["fmt", "--all"])?;
             run("cargo", &["clippy", "--all-targets", "--", "-Dwarnings"])?;
         }
+        Commands::UpdateKey { out } => {
+            if out.exists() {
+                anyhow::bail!("{} already exists; refusing to overwrite", out.display());
+            }
+            if let Some(dir) = out.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let key = SigningKey::from_bytes(&rand::random());
+            fs::write(&out, STANDARD.encode(key.to_bytes()))?;
+            println!("Wrote private key to {}", out.display());
+            println!(
+                "[update]\npublic_key = \"{}\"",
+                STANDARD.encode(key.verifying_key().as_bytes())
+            );
+        }
+        Commands::SignUpdate {
+            key,
+            version,
+            binary,
+            target,
+            dir,
+        } => {
+            let key = signing_key(&key)?;
+            let bytes = fs::read(&binary).with_context(|| format!("read {}", binary.display()))?;
+            let ext = if target.starts_with("windows") {
+                ".exe"
+            } else {
+                ""
+            };
+            let name = format!("agent-{version}-{target}{ext}");
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join(&name), &bytes)?;
+            let manifest_path = dir.join("manifest.json");
+            let mut manifest: Value = match fs::read(&manifest_path) {
+                Ok(b) => serde_json::from_slice(&b)?,
+                Err(_) => json!({ "version": version, "artifacts": [] }),
+            };
+            if manifest["version"] != version.as_str() {
+                manifest = json!({ "version": version, "artifacts": [] });
+            }
+            let artifacts = manifest["artifacts"]
+                .as_array_mut()
+                .context("manifest.json has no artifacts array")?;
+            artifacts.retain(|a| a["target"] != target.as_str());
+            artifacts.push(json!({
+                "target": target,
+                "url": format!("/updates/{name}"),
+                "signature": STANDARD.encode(key.sign(&bytes).to_bytes()),
+            }));
+            fs::write(&manifest_path, serde_json::to_vec_pretty(&manifest)?)?;
+            println!("Published {name} in {}", manifest_path.display());
+        }
+        Commands::SignConfig {
+            key,
+            version,
+            config,
+            instance,
+            group,
+            dir,
+        } => {
+            let key = signing_key(&key)?;
+            let body = fs::read_to_string(&config)
+                .with_context(|| format!("read {}", config.display()))?;
+            let signature = key.sign(format!("{version}\n{body}").as_bytes());
+            let out = match (instance, group) {
+                (Some(id), _) => dir.join("instances").join(format!("{id}.json")),
+                (None, Some(g)) => dir.join("groups").join(format!("{g}.json")),
+                (None, None) => dir.join("default.json"),
+            };
+            fs::create_dir_all(out.parent().unwrap())?;
+            let doc = json!({
+                "version": version,
+                "config": body,
+                "signature": STANDARD.encode(signature.to_bytes()),
+            });
+            fs::write(&out, serde_json::to_vec_pretty(&doc)?)?;
+            println!("Wrote {} (version {version})", out.display());
+        }
     }
     Ok(())
 }
 
+fn signing_key(path: &Path) -> Result<SigningKey> {
+    let seed: [u8; 32] = STANDARD
+        .decode(fs::read_to_string(path)?.trim())?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("{} is not a 32-byte key", path.display()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Writes `<name>.crt` and `<name>.key`: a certificate for `cn` (also its only
+/// subject name) signed by the CA.
+fn leaf(
+    dir: &Path,
+    name: &str,
+    cn: &str,
+    usage: ExtendedKeyUsagePurpose,
+    ca: &Certificate,
+    ca_key: &KeyPair,
+) -> Result<()> {
+    let key = KeyPair::generate()?;
+    let mut params = CertificateParams::new(vec![cn.to_string()])?;
+    params.distinguished_name.push(DnType::CommonName, cn);
+    params.extended_key_usages = vec![usage];
+    let cert = params.signed_by(&key, ca, ca_key)?;
+    fs::write(dir.join(format!("{name}.crt")), cert.pem())?;
+    fs::write(dir.join(format!("{name}.key")), key.serialize_pem())?;
+    Ok(())
+}
+
 fn run(cmd: &str, args: &[&str]) -> Result<()> {
     println!("+ {} {}", cmd, args.join(" "));
     let st = std::process::Command::new(cmd).args(args).status()?;